@@ -0,0 +1,35 @@
+use std::path::{Path, PathBuf};
+use crate::json_store;
+
+// Ports of serial bridges on the network, kept in a file of their own
+pub struct Bridges {
+    path: PathBuf,
+    ports: Vec<String>,
+}
+
+impl Bridges {
+    // A missing or unreadable file gives no bridges
+    pub fn load(path: &Path) -> Bridges {
+        Bridges { path: path.to_path_buf(), ports: json_store::load(path) }
+    }
+
+    pub fn ports(&self) -> &[String] {
+        &self.ports
+    }
+
+    pub fn add(&mut self, port: String) -> std::io::Result<()> {
+        if !self.ports.contains(&port) {
+            self.ports.push(port);
+        }
+        self.save()
+    }
+
+    pub fn remove(&mut self, port: &str) -> std::io::Result<()> {
+        self.ports.retain(|x| x != port);
+        self.save()
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        json_store::save(&self.path, &self.ports)
+    }
+}
@@ -0,0 +1,765 @@
+use std::collections::BTreeSet;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use clap::{Parser, Subcommand};
+use serde_json::{json, Value};
+use crate::bridges::Bridges;
+use crate::console;
+use crate::profiles::Profiles;
+use crate::preset;
+use crate::provision::Manifest;
+use crate::template;
+use crate::serial::simulator::Simulator;
+#[cfg(unix)]
+use crate::serial::transport::PtyTransport;
+use crate::serial::{list_ports, Check, Command, Direction, Event, JobStatus, Line, Settings, Snapshot, Worker, WorkerOptions};
+
+// Exit codes
+const EXIT_OK: i32 = 0;
+// the board didn't do what was asked, e.g. a menu step failed
+const EXIT_FAILED: i32 = 1;
+// bad arguments or an unreadable preset, clap uses the same
+const EXIT_USAGE: i32 = 2;
+// the port couldn't be opened or the connection broke
+const EXIT_CONNECTION: i32 = 3;
+// apply went through, but the board reports other settings
+const EXIT_MISMATCH: i32 = 4;
+
+// How often the worker is asked for events
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+// Boards may reset when the port is opened, commands wait for the sketch
+// prompt or this long without output
+const BOOT_QUIET: Duration = Duration::from_millis(500);
+const BOOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Parser)]
+#[command(name = "configurator", about = "Configures ftSwarm boards, without arguments the window opens")]
+struct Args {
+    #[arg(long, global = true, help = "Print results as JSON")]
+    json: bool,
+    #[arg(short, long, global = true, help = "Show the console output on stderr")]
+    verbose: bool,
+    #[command(subcommand)]
+    command: CliCommand,
+}
+
+#[derive(Subcommand)]
+enum CliCommand {
+    #[command(about = "Open the terminal frontend, which needs no display")]
+    Tui,
+    #[command(about = "List the serial ports and bridges")]
+    Ports,
+    #[command(about = "Look for boards on all ports")]
+    Probe,
+    #[command(about = "Write a preset to a board and verify it")]
+    Apply {
+        #[arg(help = "Preset name in the config directory, or a path")]
+        preset: String,
+        #[arg(long, help = "Write every setting, not only the changed ones")]
+        full: bool,
+        #[arg(long = "var", value_name = "NAME=VALUE", help = "Value for a placeholder of a template preset, asked for if missing")]
+        vars: Vec<String>,
+        #[command(flatten)]
+        connection: Connection,
+    },
+    #[command(about = "Write a preset to every board of a manifest and report the results")]
+    Provision {
+        #[arg(help = "TOML manifest with the base preset and one entry per board")]
+        manifest: PathBuf,
+        #[arg(long, help = "Base preset, for a CSV manifest or instead of the one in the manifest")]
+        preset: Option<String>,
+        #[arg(long, help = "Write every setting, not only the changed ones")]
+        full: bool,
+        #[arg(long, help = "Set up all boards at once instead of one after another")]
+        parallel: bool,
+    },
+    #[command(about = "Print the JSON Schema of preset files")]
+    Schema,
+    #[command(about = "Read the configuration of a board as a preset")]
+    Read {
+        #[arg(long, help = "Preset for what the menus don't show: the password, whether the board creates its swarm, and the SSID while wifi is off. Left blank without.")]
+        preset: Option<String>,
+        #[command(flatten)]
+        connection: Connection,
+    },
+    #[command(about = "Send a command and print the replies")]
+    Send {
+        command: String,
+        #[arg(long, default_value_t = 1000, help = "Milliseconds to wait for replies")]
+        wait: u64,
+        #[command(flatten)]
+        connection: Connection,
+    },
+    #[command(about = "Print what the board sends")]
+    Monitor {
+        #[arg(long, help = "Seconds to run, until interrupted without")]
+        duration: Option<u64>,
+        #[command(flatten)]
+        connection: Connection,
+    },
+    #[cfg(unix)]
+    #[command(about = "Run the demo board behind a pseudo terminal, which other programs open like a serial port")]
+    DemoTty,
+}
+
+#[derive(clap::Args)]
+struct Connection {
+    #[arg(short, long, help = "Serial port, bridge like tcp://host:port, or \"demo board\"")]
+    port: String,
+    #[arg(long, help = "Connection profile, the one chosen for the port without")]
+    profile: Option<String>,
+}
+
+// Runs the command line and returns the exit code
+pub fn run(config_dir: &Path) -> i32 {
+    let args = Args::parse();
+    let bridges = Bridges::load(&config_dir.join("network").join("bridges.json"));
+    let profiles = Profiles::load(&config_dir.join("profiles").join("profiles.json"));
+    let client = || Client::new(&bridges, args.json, args.verbose);
+
+    match args.command {
+        CliCommand::Tui => match crate::tui::run(config_dir) {
+            Ok(()) => EXIT_OK,
+            Err(e) => {
+                eprintln!("terminal frontend failed: {}", e);
+                EXIT_FAILED
+            }
+        },
+        CliCommand::Ports => client().ports(bridges.ports()),
+        CliCommand::Probe => client().probe(),
+        CliCommand::Apply { preset, full, vars, connection } => {
+            let settings = match load_preset(config_dir, &preset) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    eprintln!("can't read preset {}: {}", preset, e);
+                    return EXIT_USAGE;
+                }
+            };
+            let settings = match fill_template(&settings, &vars, &connection.port, bridges.ports()) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return EXIT_USAGE;
+                }
+            };
+            client().connected(&connection, &profiles, bridges.ports(), |client| client.apply(settings, full))
+        }
+        CliCommand::Provision { manifest: path, preset, full, parallel } => {
+            let manifest = match Manifest::load(&path, preset) {
+                Ok(manifest) => manifest,
+                Err(e) => {
+                    eprintln!("can't read manifest {}: {}", path.display(), e);
+                    return EXIT_USAGE;
+                }
+            };
+            client().provision(config_dir, &manifest, full, parallel, &profiles, &bridges)
+        }
+        CliCommand::Schema => {
+            println!("{}", serde_json::to_string_pretty(&preset::schema()).unwrap_or_default());
+            EXIT_OK
+        }
+        CliCommand::Read { preset, connection } => {
+            let base = match &preset {
+                Some(preset) => match load_preset(config_dir, preset) {
+                    Ok(settings) => settings,
+                    Err(e) => {
+                        eprintln!("can't read preset {}: {}", preset, e);
+                        return EXIT_USAGE;
+                    }
+                },
+                None => unread_blank(),
+            };
+            eprintln!(
+                "password, create_swarm and, while wifi is off, ssid aren't shown by the board: {}",
+                match &preset {
+                    Some(preset) => format!("taken from {}", preset),
+                    None => "left blank".to_string(),
+                }
+            );
+            client().connected(&connection, &profiles, bridges.ports(), |client| client.read(&base))
+        }
+        CliCommand::Send { command, wait, connection } => {
+            client().connected(&connection, &profiles, bridges.ports(), |client| client.send(&command, Duration::from_millis(wait)))
+        }
+        CliCommand::Monitor { duration, connection } => {
+            client().connected(&connection, &profiles, bridges.ports(), |client| client.monitor(duration.map(Duration::from_secs)))
+        }
+        #[cfg(unix)]
+        CliCommand::DemoTty => demo_tty(),
+    }
+}
+
+// Serves the demo board until interrupted, e.g. to try the frontends on a
+// serial port without a board
+#[cfg(unix)]
+fn demo_tty() -> i32 {
+    let pty = match PtyTransport::open() {
+        Ok(pty) => pty,
+        Err(e) => {
+            eprintln!("can't open a pseudo terminal: {}", e);
+            return EXIT_FAILED;
+        }
+    };
+    println!("{}", pty.slave_path().display());
+    let _ = Simulator::new(100).run(pty).join();
+    EXIT_OK
+}
+
+// A path, or the name of a preset in the config directory. Warnings about
+// the file go to stderr.
+fn load_preset(config_dir: &Path, preset: &str) -> Result<Settings, String> {
+    let path = Path::new(preset);
+    let path = if path.is_file() { path.to_path_buf() } else { config_dir.join(preset) };
+
+    let (settings, warnings) = preset::load(&path)?;
+    for warning in warnings {
+        eprintln!("preset {}: {}", preset, warning);
+    }
+    Ok(settings)
+}
+
+// Base of a read without a preset, the fields the menus don't show are left
+// empty instead of taking made up values
+fn unread_blank() -> Settings {
+    Settings { ssid: String::new(), password: String::new(), create_swarm: false, ..Settings::default() }
+}
+
+// Fills in the placeholders of a template preset from the board, the
+// --var options and, on a terminal, what the user types
+fn fill_template(settings: &Settings, vars: &[String], port: &str, bridges: &[String]) -> Result<Settings, String> {
+    let (_, devices) = list_ports(bridges);
+    let mut values = template::board_values(port, devices.get(port));
+    for var in vars {
+        let (name, value) = var.split_once('=').ok_or_else(|| format!("--var {} isn't NAME=VALUE", var))?;
+        values.insert(name.to_string(), value.to_string());
+    }
+
+    for name in template::variables(settings) {
+        if values.contains_key(&name) {
+            continue;
+        }
+        if !io::stdin().is_terminal() {
+            return Err(format!("no value for ${{{}}}, give it with --var {}=...", name, name));
+        }
+        eprint!("{}: ", name);
+        let mut value = String::new();
+        io::stdin().read_line(&mut value).map_err(|e| e.to_string())?;
+        values.insert(name, value.trim().to_string());
+    }
+    template::fill(settings, &values)
+}
+
+fn direction(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Rx => "rx",
+        Direction::Tx => "tx",
+        Direction::Local => "local",
+    }
+}
+
+fn line_json(line: &Line) -> Value {
+    let time = line.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+    json!({ "time": time, "direction": direction(line.direction), "text": line.text })
+}
+
+fn check_json(check: &Check) -> Value {
+    json!({ "field": check.field, "expected": check.expected, "actual": check.actual, "passed": check.passed() })
+}
+
+// One board of a manifest, worked out before any is touched
+struct Target {
+    name: String,
+    // an error if the board can't be found
+    port: Result<String, String>,
+    profile: Option<String>,
+    settings: Settings,
+}
+
+// What provisioning did on one board
+struct Outcome {
+    code: i32,
+    status: String,
+    // checks that didn't pass
+    failed: Vec<Check>,
+}
+
+// One worker driven from the command line
+struct Client {
+    worker: Worker,
+    json: bool,
+    verbose: bool,
+    // put before console lines on stderr, to tell boards apart
+    prefix: String,
+    snapshot: Snapshot,
+    // lines since the last call of take_lines()
+    lines: Vec<Line>,
+    // output after the last line ending, the worker sends it again with
+    // every read until the line is complete
+    partial: Option<Line>,
+    settings: Option<Settings>,
+    checks: Vec<Check>,
+}
+
+impl Client {
+    fn new(bridges: &Bridges, json: bool, verbose: bool) -> Client {
+        // the port given is opened right away, probing would hold it
+        let options = WorkerOptions { bridges: bridges.ports().to_vec(), manual_probe: true, ..WorkerOptions::default() };
+        let worker = Worker::spawn(Simulator::new(100), options);
+
+        Client {
+            worker,
+            json,
+            verbose,
+            prefix: String::new(),
+            snapshot: Snapshot::default(),
+            lines: vec![],
+            partial: None,
+            settings: None,
+            checks: vec![],
+        }
+    }
+
+    fn print(&self, value: Value) {
+        println!("{}", serde_json::to_string_pretty(&value).unwrap_or_default());
+    }
+
+    // Takes in the worker's events, returns the jobs among them
+    fn poll(&mut self) -> Vec<(u64, JobStatus)> {
+        let mut jobs = vec![];
+        for event in self.worker.poll() {
+            match event {
+                Event::State(snapshot) => self.snapshot = snapshot,
+                Event::Line(line) if line.direction == Direction::Rx && !line.complete => self.partial = Some(line),
+                Event::Line(line) => {
+                    if line.direction == Direction::Rx {
+                        self.partial = None;
+                    }
+                    if self.verbose {
+                        eprintln!("{}{}", self.prefix, console::format_line(&line));
+                    }
+                    self.lines.push(line);
+                }
+                Event::Settings(settings) => self.settings = Some(settings),
+                Event::Verification(checks) => self.checks = checks,
+                Event::Job(job) => jobs.push((job.id, job.status)),
+                Event::Value { .. } | Event::Bytes(_) => {}
+            }
+        }
+        jobs
+    }
+
+    // Runs a command and waits for it to finish
+    fn run(&mut self, command: Command) -> JobStatus {
+        let id = self.worker.send(command);
+        loop {
+            for (job, status) in self.poll() {
+                if job == id && status.is_finished() {
+                    return status;
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn take_lines(&mut self) -> Vec<Line> {
+        std::mem::take(&mut self.lines)
+    }
+
+    // Sends a line and collects what the board answers within `wait`, a
+    // last line without an ending too
+    fn exchange(&mut self, text: &str, wait: Duration) -> Result<Vec<String>, JobStatus> {
+        let status = self.run(crate::command(text));
+        if status != JobStatus::Succeeded {
+            return Err(status);
+        }
+
+        let deadline = Instant::now() + wait;
+        while Instant::now() < deadline && self.snapshot.connected.is_some() {
+            self.poll();
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        let mut received: Vec<Line> = self.take_lines().into_iter().filter(|x| x.direction == Direction::Rx).collect();
+        received.extend(self.partial.take());
+        Ok(received.into_iter().map(|x| x.text).collect())
+    }
+
+    // Exit code of a job that didn't succeed
+    fn failure(&self, status: &JobStatus) -> i32 {
+        if !self.json {
+            eprintln!("{}", status);
+        }
+        self.failure_code()
+    }
+
+    fn failure_code(&self) -> i32 {
+        if self.snapshot.lost.is_some() || self.snapshot.connected.is_none() {
+            EXIT_CONNECTION
+        } else {
+            EXIT_FAILED
+        }
+    }
+
+    // Exit code of a finished apply
+    fn applied(&self, status: &JobStatus) -> i32 {
+        match status {
+            JobStatus::Succeeded => EXIT_OK,
+            _ if self.checks.iter().any(|x| !x.passed()) => EXIT_MISMATCH,
+            _ => self.failure_code(),
+        }
+    }
+
+    fn ports(&mut self, bridges: &[String]) -> i32 {
+        let (ports, devices) = list_ports(bridges);
+        if self.json {
+            self.print(ports.iter().map(|port| json!({ "port": port, "device": devices.get(port) })).collect());
+        } else {
+            for port in ports {
+                match devices.get(&port) {
+                    Some(device) => println!("{}\t{}", port, device.describe()),
+                    None => println!("{}", port),
+                }
+            }
+        }
+        EXIT_OK
+    }
+
+    fn probe(&mut self) -> i32 {
+        let status = self.run(Command::Probe);
+        if status != JobStatus::Succeeded {
+            return self.failure(&status);
+        }
+
+        let probes = &self.snapshot.probes;
+        if self.json {
+            self.print(probes.iter()
+                .map(|(port, detected)| json!({ "port": port, "found": detected.to_string(), "device": self.snapshot.devices.get(port) }))
+                .collect());
+        } else {
+            for (port, detected) in probes.iter() {
+                println!("{}\t{}", port, detected);
+            }
+        }
+        EXIT_OK
+    }
+
+    // Connects, runs `operation` and disconnects again
+    fn connected(&mut self, connection: &Connection, profiles: &Profiles, bridges: &[String], operation: impl FnOnce(&mut Client) -> i32) -> i32 {
+        match self.connect(connection, profiles, bridges) {
+            Ok(()) => {}
+            Err((EXIT_CONNECTION, error)) if self.json => {
+                self.print(json!({ "port": connection.port, "error": error }));
+                return EXIT_CONNECTION;
+            }
+            Err((EXIT_CONNECTION, error)) => {
+                eprintln!("can't connect to {}: {}", connection.port, error);
+                return EXIT_CONNECTION;
+            }
+            Err((code, error)) => {
+                eprintln!("{}", error);
+                return code;
+            }
+        }
+
+        let code = operation(self);
+        if self.snapshot.connected.is_some() {
+            self.run(Command::Disconnect);
+        }
+        code
+    }
+
+    // Connects with the profile asked for, or the one chosen for the port,
+    // and lets the board boot. Errors come with their exit code.
+    fn connect(&mut self, connection: &Connection, profiles: &Profiles, bridges: &[String]) -> Result<(), (i32, String)> {
+        // profiles are chosen by the USB adapter where there is one, see
+        // port_key() in main
+        let (_, devices) = list_ports(bridges);
+        let key = devices.get(&connection.port).map_or_else(|| connection.port.clone(), |x| x.identity());
+        let profile = match &connection.profile {
+            Some(name) if !profiles.names().contains(name) => {
+                return Err((EXIT_USAGE, format!("no connection profile {}", name)));
+            }
+            Some(name) => profiles.get(name),
+            None => profiles.for_port(&key),
+        };
+
+        let status = self.run(Command::Connect(connection.port.clone(), profile));
+        if status != JobStatus::Succeeded {
+            return Err((EXIT_CONNECTION, status.to_string()));
+        }
+        self.settle();
+        self.take_lines();
+        Ok(())
+    }
+
+    fn settle(&mut self) {
+        let started = Instant::now();
+        let mut last = Instant::now();
+        let mut seen = self.lines.len();
+
+        while started.elapsed() < BOOT_TIMEOUT && last.elapsed() < BOOT_QUIET {
+            self.poll();
+            if self.lines.len() > seen {
+                if self.lines[seen..].iter().any(|x| x.direction == Direction::Rx && x.text.trim() == ">>>") {
+                    return;
+                }
+                seen = self.lines.len();
+                last = Instant::now();
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn apply(&mut self, settings: Settings, full: bool) -> i32 {
+        let status = self.run(Command::Apply(settings, full));
+
+        if self.json {
+            self.print(json!({
+                "status": status.to_string(),
+                "checks": self.checks.iter().map(check_json).collect::<Vec<_>>(),
+            }));
+        } else {
+            for check in self.checks.iter() {
+                let actual = check.actual.as_deref().unwrap_or("(not readable)");
+                let result = if check.passed() { "ok" } else { "FAIL" };
+                println!("{}: {} / {} {}", check.field, check.expected, actual, result);
+            }
+        }
+
+        match self.applied(&status) {
+            code @ (EXIT_OK | EXIT_MISMATCH) => code,
+            _ => self.failure(&status),
+        }
+    }
+
+    // Applies the base preset of a manifest with the fields of each board,
+    // a board that fails doesn't stop the others
+    fn provision(&mut self, config_dir: &Path, manifest: &Manifest, full: bool, parallel: bool, profiles: &Profiles, bridges: &Bridges) -> i32 {
+        let base = match load_preset(config_dir, &manifest.preset) {
+            Ok(base) => base,
+            Err(e) => {
+                eprintln!("can't read preset {}: {}", manifest.preset, e);
+                return EXIT_USAGE;
+            }
+        };
+
+        let (_, devices) = list_ports(bridges.ports());
+        let mut targets = vec![];
+        let mut ports = BTreeSet::new();
+        for (i, board) in manifest.boards.iter().enumerate() {
+            let port = board.find_port(&devices);
+            if let Ok(port) = &port {
+                if !ports.insert(port.clone()) {
+                    eprintln!("{} is in the manifest twice", port);
+                    return EXIT_USAGE;
+                }
+            }
+            let profile = board.profile.clone().or_else(|| manifest.profile.clone());
+            if let Some(name) = profile.as_ref().filter(|x| !profiles.names().contains(x)) {
+                eprintln!("no connection profile {}", name);
+                return EXIT_USAGE;
+            }
+            match board.settings(&base, i + 1, port.as_deref().ok(), &devices) {
+                Ok(settings) => targets.push(Target { name: board.name(), port, profile, settings }),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return EXIT_USAGE;
+                }
+            }
+        }
+
+        let outcomes: Vec<Outcome> = if parallel {
+            let (json, verbose) = (self.json, self.verbose);
+            thread::scope(|scope| {
+                let handles: Vec<_> = targets.iter()
+                    .map(|target| scope.spawn(move || Client::new(bridges, json, verbose).provision_board(target, profiles, bridges.ports(), full)))
+                    .collect();
+                handles.into_iter().map(|x| x.join().unwrap()).collect()
+            })
+        } else {
+            targets.iter().map(|target| self.provision_board(target, profiles, bridges.ports(), full)).collect()
+        };
+
+        let failed = outcomes.iter().filter(|x| x.code != EXIT_OK).count();
+        if self.json {
+            self.print(json!({
+                "boards": targets.iter().zip(outcomes.iter()).map(|(target, outcome)| json!({
+                    "board": target.name,
+                    "port": target.port.as_ref().ok(),
+                    "hostname": target.settings.hostname,
+                    "code": outcome.code,
+                    "status": outcome.status,
+                    "failed_checks": outcome.failed.iter().map(check_json).collect::<Vec<_>>(),
+                })).collect::<Vec<_>>(),
+                "succeeded": outcomes.len() - failed,
+                "failed": failed,
+            }));
+        } else {
+            for (target, outcome) in targets.iter().zip(outcomes.iter()) {
+                let result = if outcome.code == EXIT_OK { "ok" } else { &outcome.status };
+                println!("{}\t{}\t{}", target.name, target.settings.hostname, result);
+                for check in outcome.failed.iter() {
+                    println!("\t{}: {} / {}", check.field, check.expected, check.actual.as_deref().unwrap_or("(not readable)"));
+                }
+            }
+            println!("{} of {} boards set up", outcomes.len() - failed, outcomes.len());
+        }
+
+        // the code of the first board that failed
+        outcomes.iter().map(|x| x.code).find(|x| *x != EXIT_OK).unwrap_or(EXIT_OK)
+    }
+
+    fn provision_board(&mut self, target: &Target, profiles: &Profiles, bridges: &[String], full: bool) -> Outcome {
+        self.prefix = format!("{}: ", target.name);
+        let port = match &target.port {
+            Ok(port) => port.clone(),
+            Err(e) => return Outcome { code: EXIT_CONNECTION, status: e.clone(), failed: vec![] },
+        };
+
+        let connection = Connection { port, profile: target.profile.clone() };
+        if let Err((code, status)) = self.connect(&connection, profiles, bridges) {
+            return Outcome { code, status, failed: vec![] };
+        }
+
+        self.checks.clear();
+        let status = self.run(Command::Apply(target.settings.clone(), full));
+        let code = self.applied(&status);
+        if self.snapshot.connected.is_some() {
+            self.run(Command::Disconnect);
+        }
+        Outcome { code, status: status.to_string(), failed: self.checks.iter().filter(|x| !x.passed()).cloned().collect() }
+    }
+
+    // The configuration is printed as a preset, for json too
+    fn read(&mut self, base: &Settings) -> i32 {
+        match self.read_settings(base) {
+            Ok(settings) => {
+                self.print(preset::to_json(&settings));
+                EXIT_OK
+            }
+            Err(status) => self.failure(&status),
+        }
+    }
+
+    // The board's configuration, fields the menus don't show come from `base`
+    fn read_settings(&mut self, base: &Settings) -> Result<Settings, JobStatus> {
+        let status = self.run(Command::Read(base.clone()));
+        match (&status, self.settings.take()) {
+            (JobStatus::Succeeded, Some(settings)) => Ok(settings),
+            _ => Err(status),
+        }
+    }
+
+    fn send(&mut self, text: &str, wait: Duration) -> i32 {
+        let received = match self.exchange(text, wait) {
+            Ok(received) => received,
+            Err(status) => return self.failure(&status),
+        };
+
+        if self.json {
+            self.print(json!({ "sent": text, "received": received }));
+        } else {
+            for line in received.iter() {
+                println!("{}", line);
+            }
+        }
+
+        if self.snapshot.connected.is_none() {
+            return EXIT_CONNECTION;
+        }
+        EXIT_OK
+    }
+
+    // One line of output per line, JSON lines for json
+    fn monitor(&mut self, duration: Option<Duration>) -> i32 {
+        let started = Instant::now();
+        while duration.is_none_or(|x| started.elapsed() < x) {
+            self.poll();
+            for line in self.take_lines() {
+                if self.json {
+                    println!("{}", line_json(&line));
+                } else if !self.verbose {
+                    println!("{}", console::format_line(&line));
+                }
+            }
+
+            if self.snapshot.connected.is_none() {
+                return EXIT_CONNECTION;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        EXIT_OK
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::simulator::DEMO_PORT;
+    use crate::serial::transport::{MemoryTransport, Transport};
+
+    // the files don't exist, so there are no bridges and only the default
+    // profile
+    fn config_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("swarm-cli-{}", std::process::id()))
+    }
+
+    fn client() -> Client {
+        Client::new(&Bridges::load(&config_dir().join("bridges.json")), false, false)
+    }
+
+    #[test]
+    fn send_prints_the_answer_once() {
+        let mut client = client();
+        let connection = Connection { port: DEMO_PORT.to_string(), profile: None };
+        let profiles = Profiles::load(&config_dir().join("profiles.json"));
+        client.connect(&connection, &profiles, &[]).unwrap();
+
+        assert_eq!(client.exchange("mot M1 10", Duration::from_millis(300)), Ok(vec!["suc mot 10".to_string()]));
+    }
+
+    // Only what the menus show comes from the board, the rest from the base
+    #[test]
+    fn read_takes_hidden_fields_from_the_base() {
+        let mut client = client();
+        let connection = Connection { port: DEMO_PORT.to_string(), profile: None };
+        let profiles = Profiles::load(&config_dir().join("profiles.json"));
+        client.connect(&connection, &profiles, &[]).unwrap();
+
+        // the demo board is in access point mode, which shows the SSID
+        let read = client.read_settings(&unread_blank()).unwrap();
+        assert_eq!(read.password, "");
+        assert!(!read.create_swarm);
+        assert_eq!(read.ssid, "ftSwarm100");
+        assert_eq!(read.swarm_name, "ftSwarm100");
+
+        let base = Settings { password: "secret42".to_string(), create_swarm: true, ..unread_blank() };
+        let read = client.read_settings(&base).unwrap();
+        assert_eq!(read.password, "secret42");
+        assert!(read.create_swarm);
+    }
+
+    // A board whose answers arrive in pieces, as over a real serial line
+    #[test]
+    fn send_waits_for_whole_lines() {
+        let mut client = client();
+        let (host, mut board) = MemoryTransport::pair();
+        client.run(Command::Attach("board".to_string(), Box::new(host)));
+
+        let answer = thread::spawn(move || {
+            let mut command = [0; 16];
+            while board.read(&mut command).is_err() {}
+            for part in ["suc m", "ot 1", "0\r\n", "ftSwarm", "> "] {
+                board.write_all(part.as_bytes()).unwrap();
+                thread::sleep(Duration::from_millis(60));
+            }
+            board
+        });
+
+        let received = client.exchange("mot M1 10", Duration::from_millis(600));
+        assert_eq!(received, Ok(vec!["suc mot 10".to_string(), "ftSwarm> ".to_string()]));
+        answer.join().unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::serial::{Chunk, Direction, Line};
+
+// Lines kept before the oldest are dropped
+pub const CAPACITY: usize = 5000;
+// Bytes per row of the raw view
+const ROW_BYTES: usize = 16;
+
+// Bounded console log. The board's unterminated output, e.g. a prompt, is
+// shown as the last line until it is completed.
+pub struct Console {
+    lines: VecDeque<Line>,
+    partial: Option<Line>,
+    capacity: usize,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Console::with_capacity(CAPACITY)
+    }
+}
+
+impl Console {
+    pub fn with_capacity(capacity: usize) -> Console {
+        Console { lines: VecDeque::with_capacity(capacity), partial: None, capacity }
+    }
+
+    pub fn push(&mut self, line: Line) {
+        if line.direction == Direction::Rx {
+            if !line.complete {
+                self.partial = Some(line);
+                return;
+            }
+            self.partial = None;
+        }
+
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len() + self.partial.is_some() as usize
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Line> {
+        self.lines.get(index).or_else(|| self.partial.as_ref().filter(|_| index == self.lines.len()))
+    }
+}
+
+// Traffic as a hex dump with the time between reads and writes, which
+// shows how the board will split it into commands
+pub struct RawLog {
+    rows: VecDeque<String>,
+    capacity: usize,
+    last: Option<SystemTime>,
+}
+
+impl Default for RawLog {
+    fn default() -> Self {
+        RawLog::with_capacity(CAPACITY)
+    }
+}
+
+impl RawLog {
+    pub fn with_capacity(capacity: usize) -> RawLog {
+        RawLog { rows: VecDeque::with_capacity(capacity), capacity, last: None }
+    }
+
+    // "12:34:56.789   +2.5ms < 73 74 70 0a          |stp.|"
+    pub fn push(&mut self, chunk: &Chunk) {
+        let gap = match self.last.and_then(|x| chunk.time.duration_since(x).ok()) {
+            Some(gap) => format!("+{:.1}ms", gap.as_secs_f64() * 1000.0),
+            None => String::new(),
+        };
+        self.last = Some(chunk.time);
+        let head = format!("{} {:>9} {}", format_time(chunk.time), gap, marker(chunk.direction));
+
+        for (i, row) in chunk.data.chunks(ROW_BYTES).enumerate() {
+            let hex: String = row.iter().map(|x| format!("{:02x} ", x)).collect();
+            let ascii: String = row.iter().map(|&x| if (0x20..0x7f).contains(&x) { x as char } else { '.' }).collect();
+            let head = if i == 0 { head.clone() } else { " ".repeat(head.len()) };
+
+            if self.rows.len() == self.capacity {
+                self.rows.pop_front();
+            }
+            self.rows.push_back(format!("{} {:<width$}|{}|", head, hex, ascii, width = ROW_BYTES * 3));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&String> {
+        self.rows.get(index)
+    }
+}
+
+fn marker(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Rx => ">",
+        Direction::Tx => "<",
+        Direction::Local => "*",
+    }
+}
+
+// "12:34:56.789", UTC
+fn format_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    format!("{:02}:{:02}:{:02}.{:03}", secs / 3600 % 24, secs / 60 % 60, secs % 60, since_epoch.subsec_millis())
+}
+
+// "12:34:56.789 > text", the time is UTC
+pub fn format_line(line: &Line) -> String {
+    format!("{} {} {}", format_time(line.time), marker(line.direction), line.text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn line(direction: Direction, text: &str, complete: bool) -> Line {
+        Line { complete, ..Line::new(direction, text.to_string()) }
+    }
+
+    fn texts(console: &Console) -> Vec<String> {
+        (0..console.len()).map(|i| console.get(i).unwrap().text.clone()).collect()
+    }
+
+    #[test]
+    fn oldest_lines_are_dropped() {
+        let mut console = Console::with_capacity(3);
+        for text in ["one", "two", "three", "four"] {
+            console.push(line(Direction::Rx, text, true));
+        }
+        assert_eq!(texts(&console), ["two", "three", "four"]);
+
+        // the partial line doesn't count
+        console.push(line(Direction::Rx, "fi", false));
+        assert_eq!(texts(&console), ["two", "three", "four", "fi"]);
+    }
+
+    #[test]
+    fn partial_line_is_replaced() {
+        let mut console = Console::with_capacity(10);
+        console.push(line(Direction::Rx, "ftSw", false));
+        console.push(line(Direction::Rx, "ftSwarm> ", false));
+        assert_eq!(texts(&console), ["ftSwarm> "]);
+
+        // sent lines and messages go before it
+        console.push(line(Direction::Tx, "mot M1 10", true));
+        console.push(line(Direction::Local, "sent", true));
+        assert_eq!(texts(&console), ["mot M1 10", "sent", "ftSwarm> "]);
+
+        // and the completed line takes its place
+        console.push(line(Direction::Rx, "ftSwarm> suc mot 10", true));
+        assert_eq!(texts(&console), ["mot M1 10", "sent", "ftSwarm> suc mot 10"]);
+    }
+
+    #[test]
+    fn get_past_the_end() {
+        let mut console = Console::with_capacity(10);
+        assert!(console.get(0).is_none());
+        console.push(line(Direction::Rx, "one", true));
+        console.push(line(Direction::Rx, "tw", false));
+        assert_eq!(console.get(1).map(|x| x.text.as_str()), Some("tw"));
+        assert!(console.get(2).is_none());
+
+        let mut raw = RawLog::with_capacity(10);
+        assert!(raw.get(0).is_none());
+        raw.push(&Chunk { time: UNIX_EPOCH, direction: Direction::Tx, data: b"stp\n".to_vec() });
+        assert!(raw.get(1).is_none());
+    }
+
+    #[test]
+    fn raw_rows() {
+        let mut raw = RawLog::with_capacity(2);
+        let time = UNIX_EPOCH + Duration::from_millis(45_296_789);
+        raw.push(&Chunk { time, direction: Direction::Tx, data: b"stp\n".to_vec() });
+        assert_eq!(raw.get(0).unwrap(), &format!("12:34:56.789           < {:<48}|stp.|", "73 74 70 0a "));
+
+        // a row per 16 bytes, the oldest is dropped
+        let data: Vec<u8> = (b'a'..=b'z').collect();
+        raw.push(&Chunk { time: time + Duration::from_micros(2500), direction: Direction::Rx, data });
+        assert_eq!(raw.len(), 2);
+        assert!(raw.get(0).unwrap().starts_with("12:34:56.791    +2.5ms > 61 62 "), "{:?}", raw.get(0));
+        assert!(raw.get(0).unwrap().ends_with("|abcdefghijklmnop|"));
+        assert!(raw.get(1).unwrap().starts_with("                         71 72 "), "{:?}", raw.get(1));
+        assert!(raw.get(1).unwrap().ends_with("|qrstuvwxyz|"));
+    }
+}
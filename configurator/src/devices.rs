@@ -0,0 +1,42 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use crate::json_store;
+
+// What the user calls a physical board, and the preset that belongs to it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Binding {
+    pub name: String,
+    pub preset: Option<String>,
+}
+
+// Bindings by UsbDevice::identity(), kept in a file of their own
+pub struct Bindings {
+    path: PathBuf,
+    devices: BTreeMap<String, Binding>,
+}
+
+impl Bindings {
+    // A missing or unreadable file gives no bindings
+    pub fn load(path: &Path) -> Bindings {
+        Bindings { path: path.to_path_buf(), devices: json_store::load(path) }
+    }
+
+    pub fn get(&self, identity: &str) -> Option<&Binding> {
+        self.devices.get(identity)
+    }
+
+    pub fn set(&mut self, identity: &str, binding: Binding) -> std::io::Result<()> {
+        self.devices.insert(identity.to_string(), binding);
+        self.save()
+    }
+
+    pub fn remove(&mut self, identity: &str) -> std::io::Result<()> {
+        self.devices.remove(identity);
+        self.save()
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        json_store::save(&self.path, &self.devices)
+    }
+}
@@ -0,0 +1,71 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+// Settings files of the configurator that aren't presets: profiles, device
+// bindings, bridges.
+
+// A missing file gives the default. So does one that can't be read, with a
+// warning. A broken one is moved aside to "<name>.broken" first, so the next
+// save doesn't overwrite it.
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> T {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return T::default(),
+        Err(e) => {
+            eprintln!("warning: can't read {}: {}", path.display(), e);
+            return T::default();
+        }
+    };
+
+    serde_json::from_slice(&data).unwrap_or_else(|e| {
+        let broken = broken_path(path);
+        match std::fs::rename(path, &broken) {
+            Ok(()) => eprintln!("warning: {} is broken, moved to {}: {}", path.display(), broken.display(), e),
+            Err(rename) => eprintln!("warning: {} is broken: {}, can't move it aside: {}", path.display(), e, rename),
+        }
+        T::default()
+    })
+}
+
+fn broken_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".broken");
+    path.with_file_name(name)
+}
+
+pub fn save<T: Serialize>(path: &Path, value: &T) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let data = serde_json::to_string_pretty(value)?;
+    std::fs::File::create(path)?.write_all(data.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_and_load() {
+        let dir = std::env::temp_dir().join(format!("swarm-store-{}", std::process::id()));
+        let path = dir.join("network").join("bridges.json");
+        assert_eq!(load::<Vec<String>>(&path), Vec::<String>::new());
+
+        let ports = vec!["tcp://127.0.0.1:2000".to_string()];
+        save(&path, &ports).unwrap();
+        assert_eq!(load::<Vec<String>>(&path), ports);
+
+        // kept aside instead of being overwritten by the next save
+        std::fs::write(&path, "{broken").unwrap();
+        assert_eq!(load::<Vec<String>>(&path), Vec::<String>::new());
+        let broken = dir.join("network").join("bridges.json.broken");
+        assert_eq!(std::fs::read_to_string(&broken).unwrap(), "{broken");
+        assert!(!path.exists());
+        save(&path, &ports).unwrap();
+        assert_eq!(std::fs::read_to_string(&broken).unwrap(), "{broken");
+        assert_eq!(load::<Vec<String>>(&path), ports);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
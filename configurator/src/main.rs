@@ -1,30 +1,295 @@
+mod bridges;
+mod cli;
+mod console;
+mod devices;
+mod json_store;
+mod preset;
+mod profiles;
+mod provision;
 mod support;
+mod template;
+mod tui;
 mod serial;
 
 use std::borrow::Cow;
-use std::io::Write;
-use std::sync::{Mutex};
-use std::thread;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use imgui::*;
-use lazy_static::lazy_static;
-use crate::serial::{Command, Settings, Type};
-
+use crate::bridges::Bridges;
+use crate::console::{Console, RawLog};
+use crate::devices::{Binding, Bindings};
+use crate::profiles::Profiles;
+use crate::serial::simulator::Simulator;
+use crate::serial::{
+    parse_bytes, Check, Command, ConnectOptions, Direction, Event, Flow, Job, JobStatus, Line, LineEnding, ParityMode, Profile, Recording,
+    Replay, Settings, Signal, SketchCommand, Snapshot, Type, UsbDevice, Worker, WorkerOptions,
+};
+
+// Everything the windows show, updated from the worker's events
 #[derive(Default)]
 struct State {
-    console_log_lines: Vec<String>,
-    connected: bool,
-    command_queue: Vec<Command>,
+    console: Console,
+    raw: RawLog,
+    worker: Snapshot,
     port: String,
-    port_list: Vec<String>,
     current_port: usize,
     settings: Settings,
     should_apply: bool,
+    input_values: BTreeMap<String, i32>,
+    // result of the last apply
+    verification: Vec<Check>,
+    // oldest first
+    jobs: Vec<Job>,
+}
+
+// Finished jobs kept in the history
+const JOB_HISTORY: usize = 100;
+
+fn handle_event(state: &mut State, event: Event) {
+    match event {
+        Event::State(snapshot) => state.worker = snapshot,
+        Event::Line(line) => state.console.push(line),
+        Event::Bytes(chunk) => state.raw.push(&chunk),
+        Event::Value { name, value } => {
+            state.input_values.insert(name, value);
+        }
+        Event::Settings(settings) => {
+            state.settings = settings;
+            state.should_apply = true;
+        }
+        Event::Verification(checks) => state.verification = checks,
+        Event::Job(job) => {
+            match state.jobs.iter_mut().find(|x| x.id == job.id) {
+                Some(entry) => *entry = job,
+                None => state.jobs.push(job),
+            }
+
+            let finished = state.jobs.iter().filter(|x| x.status.is_finished()).count();
+            if finished > JOB_HISTORY {
+                let mut drop = finished - JOB_HISTORY;
+                state.jobs.retain(|x| {
+                    let keep = drop == 0 || !x.status.is_finished();
+                    if !keep {
+                        drop -= 1;
+                    }
+                    keep
+                });
+            }
+        }
+    }
+}
+
+// Values of the settings pane while they are edited
+#[derive(Default)]
+struct SettingsForm {
+    ssid: String,
+    password: String,
+    rgb_led_num: i32,
+    create_swarm: bool,
+    swarm_name: String,
+    swarm_pin: String,
+    hostname: String,
+    swarm_type: i32,
+    input_list: Vec<String>,
+    output_list: Vec<String>,
+    led_list: Vec<String>,
+    servo_port: String,
+    full_apply: bool,
 }
 
-// implement send
-unsafe impl Send for State {}
+impl SettingsForm {
+    fn load(&mut self, settings: &Settings) {
+        self.ssid = settings.ssid.clone();
+        self.password = settings.password.clone();
+        self.rgb_led_num = settings.rgb_led_num as i32;
+        self.create_swarm = settings.create_swarm;
+        self.swarm_name = settings.swarm_name.clone();
+        self.swarm_pin = settings.swarm_pin.clone();
+        self.hostname = settings.hostname.clone();
+        self.swarm_type = if settings.swarm_type == Type::JST { 0 } else { 1 };
+        self.input_list = settings.input_ports.clone();
+        self.output_list = settings.output_ports.clone();
+        self.led_list = settings.led_ports.clone();
+        self.servo_port = settings.servo_port.clone();
+    }
+
+    // One alias per input, motor and LED the board has
+    fn fit_lists(&mut self) {
+        self.input_list.resize((4 + self.swarm_type * 2) as usize, String::new());
+        self.output_list.resize(2, String::new());
+        self.led_list.resize(self.rgb_led_num.max(0) as usize, String::new());
+    }
+
+    fn settings(&self) -> Settings {
+        Settings {
+            ssid: self.ssid.clone(),
+            password: self.password.clone(),
+            rgb_led_num: self.rgb_led_num as u8,
+            create_swarm: self.create_swarm,
+            swarm_name: self.swarm_name.clone(),
+            swarm_pin: self.swarm_pin.clone(),
+            hostname: self.hostname.clone(),
+            swarm_type: if self.swarm_type == 0 { Type::JST } else { Type::RS485 },
+            input_ports: self.input_list.clone(),
+            output_ports: self.output_list.clone(),
+            led_ports: self.led_list.clone(),
+            servo_port: self.servo_port.clone(),
+        }
+    }
+}
 
-lazy_static!(static ref STATE: Mutex<State> = Mutex::new(State::default()););
+// One board: its own worker, console, job queue and settings pane
+struct Session {
+    id: usize,
+    worker: Worker,
+    state: State,
+    form: SettingsForm,
+    command: String,
+    // escaped bytes for "send bytes"
+    bytes: String,
+    options: ConnectOptions,
+    // one startup command per line
+    startup: String,
+    // included when a command is broadcast
+    selected: bool,
+    // session file the traffic is recorded to
+    recording: Option<PathBuf>,
+    // placeholders of a template preset being asked for before it is applied
+    template: Option<Vec<(String, String)>>,
+}
+
+impl Session {
+    // The demo boards of all sessions are in range of each other
+    fn new(id: usize, simulator: &Simulator, bridges: &Bridges) -> Session {
+        let worker = Worker::spawn(
+            simulator.neighbour(100 + id as u16),
+            WorkerOptions { bridges: bridges.ports().to_vec(), ..WorkerOptions::default() },
+        );
+
+        Session {
+            id,
+            worker,
+            state: State::default(),
+            form: SettingsForm::default(),
+            command: String::new(),
+            bytes: String::new(),
+            options: ConnectOptions::default(),
+            startup: String::new(),
+            selected: false,
+            recording: None,
+            template: None,
+        }
+    }
+
+    // The board's bound name if it has one
+    fn name(&self, bindings: &Bindings) -> String {
+        let worker = &self.state.worker;
+        let bound = |device: Option<&UsbDevice>| device.and_then(|x| bindings.get(&x.identity())).map(|x| x.name.clone());
+
+        match (&worker.connected, &worker.lost) {
+            (Some(port), _) => bound(worker.devices.get(port)).unwrap_or_else(|| port.clone()),
+            (None, Some(port)) => format!("{} (lost)", bound(worker.lost_device.as_ref()).unwrap_or_else(|| port.clone())),
+            (None, None) => format!("session {}", self.id),
+        }
+    }
+}
+
+// "Talstation: ftSwarm (sketch ready) on /dev/ttyUSB0"
+fn port_label(snapshot: &Snapshot, bindings: &Bindings, port: &str) -> String {
+    let mut label = match snapshot.probes.get(port) {
+        Some(detected) => format!("{} on {}", detected, port),
+        None => port.to_string(),
+    };
+    if let Some(binding) = snapshot.devices.get(port).and_then(|x| bindings.get(&x.identity())) {
+        label = format!("{}: {}", binding.name, label);
+    }
+    label
+}
+
+// Ports are known by their USB adapter where there is one, so settings
+// for them survive replugs
+fn port_key(snapshot: &Snapshot, port: &str) -> String {
+    match snapshot.devices.get(port) {
+        Some(device) => device.identity(),
+        None => port.to_string(),
+    }
+}
+
+// Combo box for one of a few values
+fn choose<T: Copy + PartialEq>(ui: &Ui, label: &str, value: &mut T, options: &[(T, &str)]) {
+    let mut index = options.iter().position(|x| x.0 == *value).unwrap_or(0);
+    let names = options.iter().map(|x| x.1).collect::<Vec<_>>();
+    if ui.combo_simple_string(label, &mut index, &names) {
+        *value = options[index].0;
+    }
+}
+
+// Every file in the config directory is a preset
+fn preset_names(config_dir: &Path) -> Vec<String> {
+    let mut presets = std::fs::read_dir(config_dir).unwrap();
+    let mut preset_list = Vec::new();
+    while let Some(Ok(preset)) = presets.next() {
+        if preset.file_type().unwrap().is_file() {
+            preset_list.push(preset.file_name().into_string().unwrap());
+        }
+    }
+    preset_list
+}
+
+// Puts a preset into the settings pane, what's wrong with the file goes to
+// the console
+fn open_preset(state: &mut State, config_dir: &Path, name: &str) {
+    match preset::load(&config_dir.join(name)) {
+        Ok((settings, warnings)) => {
+            for warning in warnings {
+                state.console.push(Line::new(Direction::Local, format!("preset {}: {}", name, warning)));
+            }
+            state.settings = settings;
+            state.should_apply = true;
+        }
+        Err(e) => state.console.push(Line::new(Direction::Local, format!("can't read preset {}: {}", name, e))),
+    }
+}
+
+fn save_preset(state: &mut State, config_dir: &Path, name: &str) {
+    if let Err(e) = preset::save(&config_dir.join(name), &state.settings) {
+        state.console.push(Line::new(Direction::Local, format!("can't save preset {}: {}", name, e)));
+    }
+}
+
+// Placeholder values of the connected board, see template::board_values()
+fn board_values(snapshot: &Snapshot) -> BTreeMap<String, String> {
+    match &snapshot.connected {
+        Some(port) => template::board_values(port, snapshot.devices.get(port)),
+        None => BTreeMap::new(),
+    }
+}
+
+// Session files, newest first
+fn recording_names(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .map(|entries| entries.flatten().filter_map(|x| x.file_name().into_string().ok()).filter(|x| x.ends_with(".jsonl")).collect())
+        .unwrap_or_default();
+    names.sort();
+    names.reverse();
+    names
+}
+
+fn command(text: &str) -> Command {
+    match text.parse::<SketchCommand>() {
+        Ok(sketch) => Command::Sketch(sketch),
+        Err(_) => Command::Send(text.to_string()),
+    }
+}
+
+fn has_display() -> bool {
+    if cfg!(all(unix, not(target_os = "macos"))) {
+        std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some()
+    } else {
+        true
+    }
+}
 
 fn main() {
     // check for a config directory in the user's home directory
@@ -34,188 +299,427 @@ fn main() {
         std::fs::create_dir(&config_dir).unwrap();
     }
 
+    // with arguments it runs headless
+    if std::env::args_os().len() > 1 {
+        std::process::exit(cli::run(&config_dir));
+    }
+
+    // no OpenGL without a display, e.g. over SSH
+    if !has_display() {
+        if let Err(e) = tui::run(&config_dir) {
+            eprintln!("terminal frontend failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let system = support::init("swarm configurator");
-    let mut command = String::new();
     let mut new_preset_name = String::new();
-
-    let mut ssid: String = String::new();
-    let mut password: String = String::new();
-    let mut rgb_led_num: i32 = 0;
-    let mut create_swarm: bool = false;
-    let mut swarm_name: String = String::new();
-    let mut swarm_pin: String = String::new();
-    let mut hostname: String = String::new();
-    let mut swarm_type: i32 = 0;
-    let mut input_list: Vec<String> = vec![];
-    let mut output_list: Vec<String> = vec![];
-    let mut led_list: Vec<String> = vec![];
-    let mut servo_port: String = String::new();
     let mut current_preset = 0;
-
-    thread::spawn(move || {
-        serial::serial_thread();
-    });
+    let mut broadcast = String::new();
+    // hex dump instead of lines in the console
+    let mut raw_view = false;
+
+    // friendly names and presets of boards, not a preset itself
+    let mut bindings = Bindings::load(&config_dir.join("devices").join("bindings.json"));
+    // device being edited in the devices window
+    let mut edited_device: Option<UsbDevice> = None;
+    let mut device_name = String::new();
+    let mut device_preset = 0;
+
+    let mut profiles = Profiles::load(&config_dir.join("profiles").join("profiles.json"));
+    // profile being edited in the profiles window
+    let mut edited_profile = Profile::default();
+    let mut new_profile_name = String::new();
+
+    // session files, kept apart from the presets
+    let recordings_dir = config_dir.join("recordings");
+    let mut selected_recording: Option<String> = None;
+
+    let mut bridges = Bridges::load(&config_dir.join("network").join("bridges.json"));
+    let mut bridge_address = String::new();
+    let mut bridge_rfc2217 = false;
+
+    let simulator = Simulator::new(100);
+    let mut sessions = vec![Session::new(1, &simulator, &bridges)];
+    let mut next_session = 2;
+    let mut active = 0;
+    // tab to bring to the front in the next frame
+    let mut select_tab: Option<usize> = None;
 
     system.main_loop(move |_, ui| {
+        for session in sessions.iter_mut() {
+            for event in session.worker.poll() {
+                handle_event(&mut session.state, event);
+            }
+        }
+
+        ui.window("sessions")
+            .size([400.0, 200.0], Condition::FirstUseEver)
+            .position([ui.io().display_size[0] - 450.0, 350.0], Condition::FirstUseEver)
+            .build(|| {
+                let mut closed = None;
+                if let Some(_tabs) = ui.tab_bar("session tabs") {
+                    for (i, session) in sessions.iter().enumerate() {
+                        let label = format!("{}###session{}", session.name(&bindings), session.id);
+                        let flags = if select_tab == Some(i) { TabItemFlags::SET_SELECTED } else { TabItemFlags::empty() };
+                        let mut open = true;
+                        let mut tab = TabItem::new(label).flags(flags);
+                        // the last session stays open
+                        if sessions.len() > 1 {
+                            tab = tab.opened(&mut open);
+                        }
+                        if tab.begin(ui).is_some() {
+                            active = i;
+                        }
+                        if !open {
+                            closed = Some(i);
+                        }
+                    }
+                }
+                select_tab = None;
+
+                if let Some(i) = closed {
+                    sessions.remove(i);
+                    if active >= i && active > 0 {
+                        active -= 1;
+                    }
+                    select_tab = Some(active);
+                }
+
+                if ui.button("new session") {
+                    sessions.push(Session::new(next_session, &simulator, &bridges));
+                    next_session += 1;
+                    select_tab = Some(sessions.len() - 1);
+                }
+
+                ui.separator();
+                ui.text("broadcast to:");
+                for session in sessions.iter_mut() {
+                    let _d = ui.begin_enabled(session.state.worker.connected.is_some());
+                    ui.checkbox(format!("{}##broadcast{}", session.name(&bindings), session.id), &mut session.selected);
+                }
+
+                ui.input_text("##broadcast", &mut broadcast).build();
+                ui.same_line();
+                if ui.button("send to selected") {
+                    for session in sessions.iter_mut() {
+                        if session.selected && session.state.worker.connected.is_some() {
+                            session.worker.send(command(&broadcast));
+                        }
+                    }
+                    broadcast.clear();
+                }
+            });
+
+        ui.window("network bridges")
+            .size([400.0, 200.0], Condition::FirstUseEver)
+            .position([ui.io().display_size[0] - 450.0, 250.0], Condition::FirstUseEver)
+            .build(|| {
+                let mut result = None;
+                for port in bridges.ports().to_vec() {
+                    ui.text(&port);
+                    ui.same_line();
+                    if ui.button(format!("remove##{}", port)) {
+                        result = Some(bridges.remove(&port));
+                    }
+                }
+                if bridges.ports().is_empty() {
+                    ui.text("no bridges, e.g. ser2net on another computer");
+                }
+
+                ui.separator();
+                ui.input_text("host:port", &mut bridge_address).build();
+                ui.checkbox("RFC 2217 (line parameters from the profile)", &mut bridge_rfc2217);
+                let _d = ui.begin_enabled(!bridge_address.trim().is_empty());
+                if ui.button("add bridge") {
+                    let scheme = if bridge_rfc2217 { "rfc2217" } else { "tcp" };
+                    result = Some(bridges.add(format!("{}://{}", scheme, bridge_address.trim())));
+                    bridge_address.clear();
+                }
+                drop(_d);
+
+                match result {
+                    Some(Ok(())) => {
+                        for session in sessions.iter() {
+                            session.worker.set_bridges(bridges.ports().to_vec());
+                        }
+                    }
+                    Some(Err(e)) => {
+                        sessions[active].state.console.push(Line::new(Direction::Local, format!("could not save the bridges: {}", e)));
+                    }
+                    None => {}
+                }
+            });
+
+        ui.window("recordings")
+            .size([400.0, 250.0], Condition::FirstUseEver)
+            .position([ui.io().display_size[0] - 450.0, 600.0], Condition::FirstUseEver)
+            .build(|| {
+                let session = &mut sessions[active];
+                match &session.recording {
+                    Some(path) => {
+                        ui.text(format!("recording to {}", path.display()));
+                        if ui.button("stop recording") {
+                            session.worker.stop_recording();
+                            session.recording = None;
+                        }
+                    }
+                    None => {
+                        if ui.button("start recording") {
+                            let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+                            let path = recordings_dir.join(format!("session-{}.jsonl", time));
+                            let port = session.state.worker.connected.clone().unwrap_or_default();
+                            match session.worker.start_recording(&path, &port) {
+                                Ok(()) => session.recording = Some(path),
+                                Err(e) => session.state.console.push(Line::new(Direction::Local, format!("can't record: {}", e))),
+                            }
+                        }
+                    }
+                }
+
+                ui.separator();
+                for name in recording_names(&recordings_dir) {
+                    let selected = selected_recording.as_ref() == Some(&name);
+                    if ui.selectable_config(&name).selected(selected).build() {
+                        selected_recording = Some(name);
+                    }
+                }
+
+                let Some(name) = &selected_recording else { return };
+                let path = recordings_dir.join(name);
+
+                // both open a session of their own, the current one is left alone
+                let open = ui.button("open in console");
+                ui.same_line();
+                let replay = ui.button("replay as board");
+                ui.same_line();
+                let export = ui.button("export pcapng");
+
+                let mut message = None;
+                let mut start = None;
+                if open {
+                    start = Some(Command::Replay(path.clone()));
+                }
+                if replay {
+                    match Recording::load(&path) {
+                        Ok(recording) => start = Some(Command::Attach(format!("replay of {}", name), Box::new(Replay::new(recording)))),
+                        Err(e) => message = Some(format!("can't load {}: {}", name, e)),
+                    }
+                }
+                if let Some(command) = start {
+                    let mut session = Session::new(next_session, &simulator, &bridges);
+                    next_session += 1;
+                    session.worker.send(command);
+                    sessions.push(session);
+                    select_tab = Some(sessions.len() - 1);
+                }
+                if export {
+                    let target = path.with_extension("pcapng");
+                    message = Some(match Recording::load(&path).and_then(|x| x.export_pcapng(&target)) {
+                        Ok(()) => format!("exported {}", target.display()),
+                        Err(e) => format!("can't export {}: {}", name, e),
+                    });
+                }
+                if let Some(message) = message {
+                    sessions[active].state.console.push(Line::new(Direction::Local, message));
+                }
+            });
+
+        let Session { worker, state, form, command: command_text, bytes, options, startup, template: template_values, .. } = &mut sessions[active];
+
         let tile_width = ui.io().display_size[0] / 2.0 - 75.0;
 
         ui.window("controls")
-            .size([tile_width, 200.0], Condition::Always)
+            .size([tile_width, 260.0], Condition::Always)
             .position([50.0, 50.0], Condition::Always)
             .flags(WindowFlags::NO_RESIZE | WindowFlags::NO_MOVE | WindowFlags::NO_COLLAPSE)
 
             .build(|| {
-                let mut state = STATE.lock().unwrap();
-
-                let _d = ui.begin_enabled(!state.connected);
-
-                // Drop down menu for selecting serial port
-                let mut port_list = state.port_list.clone();
+                let connected = state.worker.connected.is_some();
+                let _d = ui.begin_enabled(!connected);
+
+                // Drop down menu for selecting serial port, annotated with
+                // what was found on each
+                let mut port_list = state.worker.ports.iter()
+                    .map(|port| port_label(&state.worker, &bindings, port))
+                    .collect::<Vec<_>>();
                 port_list.insert(0, "Select a port".to_string());
                 let port_list = port_list.iter().map(|x| x.as_str()).collect::<Vec<_>>();
                 ui.combo(" ", &mut state.current_port, &port_list, |x| Cow::Owned(x.to_string()));
 
-                if state.current_port != 0 && state.current_port <= state.port_list.len() {
-                    state.port = state.port_list[state.current_port - 1].clone();
+                if state.current_port != 0 && state.current_port <= state.worker.ports.len() {
+                    state.port = state.worker.ports[state.current_port - 1].clone();
+                }
+
+                // line parameters for the selected port
+                let key = port_key(&state.worker, &state.port);
+                let names = profiles.names();
+                let mut profile = names.iter().position(|x| *x == profiles.name_for_port(&key)).unwrap_or(0);
+                if ui.combo_simple_string("profile", &mut profile, &names) {
+                    if let Err(e) = profiles.assign(&key, &names[profile]) {
+                        state.console.push(Line::new(Direction::Local, format!("could not save the profiles: {}", e)));
+                    }
                 }
 
                 drop(_d);
 
-                let _d = ui.begin_enabled(state.command_queue.len() == 0);
+                let _d = ui.begin_enabled(!state.worker.busy);
+
+                if !connected && ui.button("connect") {
+                    // a board with a preset of its own gets it in the form
+                    let device = state.worker.devices.get(&state.port);
+                    let preset = device.and_then(|x| bindings.get(&x.identity())).and_then(|x| x.preset.clone());
+                    if let Some(preset) = preset {
+                        open_preset(state, &config_dir, &preset);
+                    }
+                    worker.send(Command::Connect(state.port.clone(), profiles.for_port(&key)));
+                }
 
-                if (!state.connected) && ui.button("connect") {
-                    state.command_queue.push(Command::Connect);
+                if !connected {
+                    ui.same_line();
+                    if ui.button("probe ports") {
+                        worker.send(Command::Probe);
+                    }
                 }
 
-                if state.connected && ui.button("disconnect") {
-                    state.command_queue.push(Command::Disconnect);
+                if connected && ui.button("disconnect") {
+                    worker.send(Command::Disconnect);
                 }
 
                 drop(_d);
-                let _d = ui.begin_enabled(state.connected);
+
+                if let Some(port) = &state.worker.lost {
+                    let waiting = if options.reconnect { ", waiting for it to come back" } else { "" };
+                    ui.text_colored([1.0, 0.8, 0.3, 1.0], format!("lost connection to {}{}", port, waiting));
+                }
+
+                let mut changed = ui.checkbox("reconnect automatically", &mut options.reconnect);
+                ui.same_line();
+                changed |= ui.checkbox("re-run startup commands", &mut options.rerun_startup);
+                changed |= ui.input_text_multiline("startup commands", startup, [0.0, 50.0]).build();
+                if changed {
+                    options.startup = startup.lines().map(|x| x.to_string()).collect();
+                    worker.set_options(options.clone());
+                }
+
+                let _d = ui.begin_enabled(connected);
 
                 // input field for a command
-                ui.input_text("command", &mut command).build();
+                ui.input_text("command", command_text).build();
 
                 if ui.button("send") {
-                    state.command_queue.push(Command::Send(command.clone()));
-                    command.clear();
+                    worker.send(command(command_text));
+                    command_text.clear();
+                }
+
+                ui.input_text("bytes, e.g. stp\\x0a", bytes).build();
+                if ui.button("send bytes") {
+                    match parse_bytes(bytes) {
+                        Ok(data) => {
+                            worker.send(Command::SendBytes(data));
+                            bytes.clear();
+                        }
+                        Err(e) => state.console.push(Line::new(Direction::Local, format!("can't send bytes: {}", e))),
+                    }
+                }
+
+                for (name, value) in state.input_values.iter() {
+                    ui.text(format!("{}: {}", name, value));
+                }
+
+                drop(_d);
+                if let Some(error) = &state.worker.last_error {
+                    ui.text_colored([1.0, 0.3, 0.3, 1.0], error);
                 }
 
-                drop(state);
             });
 
         ui.window("swarm configurator")
-            .size([tile_width, ui.io().display_size[1] / 2.0 - 175.0], Condition::Always)
-            .position([50.0, 300.0], Condition::Always)
+            .size([tile_width, ui.io().display_size[1] / 2.0 - 235.0], Condition::Always)
+            .position([50.0, 360.0], Condition::Always)
             .flags(WindowFlags::NO_RESIZE | WindowFlags::NO_MOVE | WindowFlags::NO_COLLAPSE)
             .build(|| {
-                let mut state = STATE.lock().unwrap();
-
                 if state.should_apply {
-                    ssid = state.settings.ssid.clone();
-                    password = state.settings.password.clone();
-                    rgb_led_num = state.settings.rgb_led_num as i32;
-                    create_swarm = state.settings.create_swarm;
-                    swarm_name = state.settings.swarm_name.clone();
-                    swarm_pin = state.settings.swarm_pin.clone();
-                    hostname = state.settings.hostname.clone();
-                    swarm_type = if state.settings.swarm_type == Type::JST { 0 } else { 1 };
-                    input_list = state.settings.input_ports.clone();
-                    output_list = state.settings.output_ports.clone();
-                    led_list = state.settings.led_ports.clone();
-                    servo_port = state.settings.servo_port.clone();
-
+                    form.load(&state.settings);
                     state.should_apply = false;
                 }
 
-                ui.input_text("ssid", &mut ssid).build();
-                ui.input_text("password", &mut password).build();
-                ui.input_int("rgb led num", &mut rgb_led_num).build();
-                ui.checkbox("create swarm", &mut create_swarm);
-                ui.input_text("swarm name", &mut swarm_name).build();
-                ui.input_text("swarm pin", &mut swarm_pin).build();
-                ui.input_text("hostname", &mut hostname).build();
-                ui.input_int("swarm type (0 for JST, 1 for RS485)", &mut swarm_type).build();
+                ui.input_text("ssid", &mut form.ssid).build();
+                ui.input_text("password", &mut form.password).build();
+                ui.input_int("rgb led num", &mut form.rgb_led_num).build();
+                ui.checkbox("create swarm", &mut form.create_swarm);
+                ui.input_text("swarm name", &mut form.swarm_name).build();
+                ui.input_text("swarm pin", &mut form.swarm_pin).build();
+                ui.input_text("hostname", &mut form.hostname).build();
+                ui.input_int("swarm type (0 for JST, 1 for RS485)", &mut form.swarm_type).build();
 
                 ui.separator();
                 ui.text("aliases:");
 
-                let input_list_len_should_be = (4 + swarm_type * 2) as usize;
+                form.fit_lists();
 
-                if input_list.len() > input_list_len_should_be {
-                    input_list.truncate(input_list_len_should_be);
-                } else if input_list.len() < input_list_len_should_be {
-                    for _ in input_list.len()..input_list_len_should_be {
-                        input_list.push(String::new());
-                    }
-                }
-
-                let mut i = 1;
-                for mut input in input_list.iter_mut() {
-                    let name = format!("A{}", i);
+                for (i, input) in form.input_list.iter_mut().enumerate() {
+                    let name = format!("A{}", i + 1);
                     let name: &'static str = Box::leak(name.into_boxed_str());
                     ui.input_text(name, input).build();
-                    i += 1;
                 }
 
-                if output_list.len() > 2 {
-                    output_list.truncate(2);
-                } else if output_list.len() < 2 {
-                    for _ in output_list.len()..2 {
-                        output_list.push(String::new());
-                    }
+                for (i, output) in form.output_list.iter_mut().enumerate() {
+                    let name = format!("M{}", i + 1);
+                    let name: &'static str = Box::leak(name.into_boxed_str());
+                    ui.input_text(name, output).build();
                 }
 
-                let mut i = 1;
-                for mut output in output_list.iter_mut() {
-                    let name = format!("M{}", i);
+                for (i, led) in form.led_list.iter_mut().enumerate() {
+                    let name = format!("LED{}", i + 1);
                     let name: &'static str = Box::leak(name.into_boxed_str());
-                    ui.input_text(name, output).build();
-                    i += 1;
+                    ui.input_text(name, led).build();
                 }
 
-                let rgb_list_len_should_be = rgb_led_num as usize;
+                ui.input_text("SERVO", &mut form.servo_port).build();
+
+                let settings = form.settings();
 
-                if led_list.len() > rgb_list_len_should_be {
-                    led_list.truncate(rgb_list_len_should_be);
-                } else if led_list.len() < rgb_list_len_should_be {
-                    for _ in led_list.len()..rgb_list_len_should_be {
-                        led_list.push(String::new());
+                state.settings = settings.clone();
+
+                let _d = ui.begin_enabled(state.worker.connected.is_some());
+                if ui.button("apply") {
+                    let values = board_values(&state.worker);
+                    let missing: Vec<(String, String)> = template::variables(&settings)
+                        .into_iter()
+                        .filter(|x| !values.contains_key(x))
+                        .map(|x| (x, String::new()))
+                        .collect();
+                    if missing.is_empty() {
+                        match template::fill(&settings, &values) {
+                            Ok(settings) => {
+                                worker.send(Command::Apply(settings, form.full_apply));
+                            }
+                            Err(e) => state.console.push(Line::new(Direction::Local, e)),
+                        }
+                    } else {
+                        *template_values = Some(missing);
                     }
                 }
 
-                let mut i = 1;
-                for mut led in led_list.iter_mut() {
-                    let name = format!("LED{}", i);
-                    let name: &'static str = Box::leak(name.into_boxed_str());
-                    ui.input_text(name, led).build();
-                    i += 1;
-                }
-
-                ui.input_text("SERVO", &mut servo_port).build();
-
-                let settings = Settings {
-                    ssid: ssid.clone(),
-                    password: password.clone(),
-                    rgb_led_num: rgb_led_num.clone() as u8,
-                    create_swarm: create_swarm.clone(),
-                    swarm_name: swarm_name.clone(),
-                    swarm_pin: swarm_pin.clone(),
-                    hostname: hostname.clone(),
-                    swarm_type: if swarm_type == 0 { Type::JST } else { Type::RS485 },
-                    input_ports: input_list.clone(),
-                    output_ports: output_list.clone(),
-                    led_ports: led_list.clone(),
-                    servo_port: servo_port.clone(),
-                };
+                ui.same_line();
+                ui.checkbox("rewrite everything", &mut form.full_apply);
 
-                state.settings = settings.clone();
+                ui.same_line();
+                if ui.button("read from board") {
+                    worker.send(Command::Read(state.settings.clone()));
+                }
+                drop(_d);
 
-                if ui.button("apply") {
-                    state.command_queue.push(Command::Apply(settings));
+                ui.same_line();
+                let _d = ui.begin_enabled(state.worker.busy);
+                if ui.button("cancel") {
+                    if let Some(job) = state.jobs.iter().find(|x| x.status == JobStatus::Running) {
+                        worker.cancel(job.id);
+                    }
                 }
-                drop(state);
+                drop(_d);
             });
 
         ui.window("presets")
@@ -223,16 +727,7 @@ fn main() {
             .position([50.0, ui.io().display_size[1] / 2.0 + 175.0], Condition::Always)
             .flags(WindowFlags::NO_RESIZE | WindowFlags::NO_MOVE | WindowFlags::NO_COLLAPSE)
             .build(|| {
-                // Get all the files in the config directory
-                let mut state = STATE.lock().unwrap();
-                let mut presets = std::fs::read_dir(&config_dir).unwrap();
-                let mut preset_list = Vec::new();
-                while let Some(Ok(preset)) = presets.next() {
-                    if preset.file_type().unwrap().is_file() {
-                        preset_list.push(preset.file_name().into_string().unwrap());
-                    }
-                }
-
+                let preset_list = preset_names(&config_dir);
                 let mut preset_list = preset_list.iter().map(|x| x.as_str()).collect::<Vec<_>>();
                 preset_list.insert(0, "Select a preset");
 
@@ -244,19 +739,11 @@ fn main() {
                 }
 
                 if ui.button("load") {
-                    if let Ok(file) = std::fs::File::open(config_dir.join(&preset_name)) {
-                        let reader = std::io::BufReader::new(file);
-                        let settings: Settings = serde_json::from_reader(reader).unwrap();
-                        state.settings = settings.clone();
-                        state.should_apply = true;
-                    }
+                    open_preset(state, &config_dir, &preset_name);
                 }
 
                 if ui.button("save") {
-                    if let Ok(str) = serde_json::to_string(&state.settings) {
-                        let mut file = std::fs::File::create(config_dir.join(&preset_name)).unwrap();
-                        file.write_all(str.as_bytes()).unwrap();
-                    }
+                    save_preset(state, &config_dir, &preset_name);
                 }
 
                 if ui.button("delete") {
@@ -268,15 +755,10 @@ fn main() {
                 ui.input_text("new preset name", &mut new_preset_name).build();
 
                 if ui.button("new") {
-
-                    if let Ok(str) = serde_json::to_string(&state.settings) {
-                        let mut file = std::fs::File::create(config_dir.join(&new_preset_name)).unwrap();
-                        file.write_all(str.as_bytes()).unwrap();
-                        new_preset_name = String::new();
-                    }
+                    save_preset(state, &config_dir, &new_preset_name);
+                    new_preset_name = String::new();
                 }
 
-                drop(state);
             });
 
         ui.window("console log")
@@ -284,11 +766,253 @@ fn main() {
             .position([ui.io().display_size[0] / 2.0 + 25.0, 50.0], Condition::Always)
             .flags(WindowFlags::NO_RESIZE | WindowFlags::NO_MOVE | WindowFlags::NO_COLLAPSE)
             .build(|| {
-                let mut state = STATE.lock().unwrap();
-                for x in state.console_log_lines.clone().iter().rev().into_iter().take(80) {
-                    ui.text(x);
+                ui.checkbox("raw bytes", &mut raw_view);
+
+                ui.child_window("log").build(|| {
+                    // follow new lines unless scrolled up
+                    let follow = ui.scroll_y() >= ui.scroll_max_y();
+
+                    if raw_view {
+                        let clipper = ListClipper::new(state.raw.len() as i32).begin(ui);
+                        for i in clipper.iter() {
+                            if let Some(row) = state.raw.get(i as usize) {
+                                ui.text(row);
+                            }
+                        }
+                    } else {
+                        let clipper = ListClipper::new(state.console.len() as i32).begin(ui);
+                        for i in clipper.iter() {
+                            if let Some(line) = state.console.get(i as usize) {
+                                ui.text(console::format_line(line));
+                            }
+                        }
+                    }
+
+                    if follow {
+                        ui.set_scroll_here_y_with_ratio(1.0);
+                    }
+                });
+            });
+
+        ui.window("devices")
+            .size([400.0, 200.0], Condition::FirstUseEver)
+            .position([ui.io().display_size[0] - 450.0, 100.0], Condition::FirstUseEver)
+            .build(|| {
+                let preset_list = preset_names(&config_dir);
+
+                for (port, device) in state.worker.devices.iter() {
+                    let binding = bindings.get(&device.identity());
+                    let mut label = format!("{}: {}", port, device.describe());
+                    if let Some(binding) = binding {
+                        label = format!("{} - {}", binding.name, label);
+                    }
+
+                    let edited = edited_device.as_ref().is_some_and(|x| x.identity() == device.identity());
+                    if ui.selectable_config(label).selected(edited).build() {
+                        let binding = binding.cloned().unwrap_or_default();
+                        device_name = binding.name;
+                        device_preset = binding.preset
+                            .and_then(|x| preset_list.iter().position(|y| *y == x))
+                            .map_or(0, |x| x + 1);
+                        edited_device = Some(device.clone());
+                    }
+                }
+
+                if state.worker.devices.is_empty() {
+                    ui.text("no USB serial adapters found");
+                }
+
+                let Some(device) = &edited_device else { return };
+                ui.separator();
+                ui.text(device.describe());
+                ui.input_text("name", &mut device_name).build();
+
+                let mut preset_choice = preset_list.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+                preset_choice.insert(0, "no preset");
+                ui.combo("preset", &mut device_preset, &preset_choice, |x| Cow::Owned(x.to_string()));
+
+                let _d = ui.begin_enabled(!device_name.trim().is_empty());
+                if ui.button("bind") {
+                    let binding = Binding {
+                        name: device_name.trim().to_string(),
+                        preset: device_preset.checked_sub(1).and_then(|x| preset_list.get(x)).cloned(),
+                    };
+                    if let Err(e) = bindings.set(&device.identity(), binding) {
+                        state.console.push(Line::new(Direction::Local, format!("could not save the device names: {}", e)));
+                    }
+                }
+                drop(_d);
+
+                ui.same_line();
+                if ui.button("forget") {
+                    if let Err(e) = bindings.remove(&device.identity()) {
+                        state.console.push(Line::new(Direction::Local, format!("could not save the device names: {}", e)));
+                    }
+                    device_name.clear();
+                    device_preset = 0;
+                }
+            });
+
+        ui.window("connection profiles")
+            .size([400.0, 300.0], Condition::FirstUseEver)
+            .position([ui.io().display_size[0] - 450.0, 150.0], Condition::FirstUseEver)
+            .build(|| {
+                let names = profiles.names();
+                let mut selected = names.iter().position(|x| *x == edited_profile.name).unwrap_or(0);
+                if ui.combo_simple_string("edit", &mut selected, &names) {
+                    edited_profile = profiles.get(&names[selected]);
+                }
+
+                let mut baud_rate = edited_profile.baud_rate as i32;
+                if ui.input_int("baud rate", &mut baud_rate).build() {
+                    edited_profile.baud_rate = baud_rate.max(1) as u32;
+                }
+                choose(ui, "data bits", &mut edited_profile.data_bits, &[(5, "5"), (6, "6"), (7, "7"), (8, "8")]);
+                choose(ui, "parity", &mut edited_profile.parity, &[
+                    (ParityMode::None, "none"),
+                    (ParityMode::Odd, "odd"),
+                    (ParityMode::Even, "even"),
+                ]);
+                choose(ui, "stop bits", &mut edited_profile.stop_bits, &[(1, "1"), (2, "2")]);
+                choose(ui, "flow control", &mut edited_profile.flow_control, &[
+                    (Flow::None, "none"),
+                    (Flow::XonXoff, "XON/XOFF"),
+                    (Flow::RtsCts, "RTS/CTS"),
+                ]);
+                let signals = [(Signal::Unchanged, "unchanged"), (Signal::Set, "set"), (Signal::Cleared, "cleared")];
+                choose(ui, "DTR on open", &mut edited_profile.dtr, &signals);
+                choose(ui, "RTS on open", &mut edited_profile.rts, &signals);
+                choose(ui, "line ending", &mut edited_profile.line_ending, &[(LineEnding::Lf, "\\n"), (LineEnding::CrLf, "\\r\\n")]);
+
+                let mut result = Ok(());
+                if ui.button("save") {
+                    result = profiles.set(edited_profile.clone());
+                }
+                ui.same_line();
+                let _d = ui.begin_enabled(edited_profile.name != Profile::default().name);
+                if ui.button("delete") {
+                    result = profiles.remove(&edited_profile.name);
+                    edited_profile = Profile::default();
+                }
+                drop(_d);
+
+                ui.separator();
+                ui.input_text("new profile name", &mut new_profile_name).build();
+                let _d = ui.begin_enabled(!new_profile_name.trim().is_empty());
+                if ui.button("new") {
+                    edited_profile.name = new_profile_name.trim().to_string();
+                    result = profiles.set(edited_profile.clone());
+                    new_profile_name.clear();
+                }
+                drop(_d);
+
+                if let Err(e) = result {
+                    state.console.push(Line::new(Direction::Local, format!("could not save the profiles: {}", e)));
+                }
+            });
+
+        ui.window("jobs")
+            .size([400.0, 250.0], Condition::FirstUseEver)
+            .position([ui.io().display_size[0] - 450.0, ui.io().display_size[1] - 600.0], Condition::FirstUseEver)
+            .build(|| {
+                for job in state.jobs.iter().rev() {
+                    ui.text(format!("#{} {} - {}", job.id, job.label, job.status));
+
+                    if !job.status.is_finished() {
+                        if let Some(progress) = job.progress {
+                            ProgressBar::new(progress).size([200.0, 0.0]).build(ui);
+                            ui.same_line();
+                        }
+                        if ui.button(format!("cancel##{}", job.id)) {
+                            worker.cancel(job.id);
+                        }
+                    }
                 }
-                drop(state);
             });
+
+        if let Some(values) = template_values {
+            let mut close = false;
+            ui.window("template variables")
+                .size([400.0, 200.0], Condition::FirstUseEver)
+                .position([ui.io().display_size[0] / 2.0 - 200.0, 300.0], Condition::FirstUseEver)
+                .build(|| {
+                    ui.text("the preset is a template, fill in its placeholders:");
+                    for (name, value) in values.iter_mut() {
+                        ui.input_text(name.as_str(), value).build();
+                    }
+
+                    let _d = ui.begin_enabled(state.worker.connected.is_some());
+                    if ui.button("apply") {
+                        let mut filled = board_values(&state.worker);
+                        filled.extend(values.iter().cloned());
+                        match template::fill(&state.settings, &filled) {
+                            Ok(settings) => {
+                                worker.send(Command::Apply(settings, form.full_apply));
+                            }
+                            Err(e) => state.console.push(Line::new(Direction::Local, e)),
+                        }
+                        close = true;
+                    }
+                    drop(_d);
+
+                    ui.same_line();
+                    if ui.button("cancel") {
+                        close = true;
+                    }
+                });
+            if close {
+                *template_values = None;
+            }
+        }
+
+        if !state.verification.is_empty() {
+            ui.window("verification")
+                .size([400.0, 300.0], Condition::FirstUseEver)
+                .position([ui.io().display_size[0] - 450.0, 50.0], Condition::FirstUseEver)
+                .build(|| {
+                    if let Some(_table) = ui.begin_table_with_flags("checks", 4, TableFlags::BORDERS | TableFlags::ROW_BG) {
+                        ui.table_setup_column("field");
+                        ui.table_setup_column("expected");
+                        ui.table_setup_column("actual");
+                        ui.table_setup_column("");
+                        ui.table_headers_row();
+
+                        for check in state.verification.iter() {
+                            ui.table_next_column();
+                            ui.text(&check.field);
+                            ui.table_next_column();
+                            ui.text(&check.expected);
+                            ui.table_next_column();
+                            ui.text(check.actual.as_deref().unwrap_or("(not readable)"));
+                            ui.table_next_column();
+                            if !check.passed() {
+                                ui.text_colored([1.0, 0.3, 0.3, 1.0], "FAIL");
+                            } else if check.actual.is_some() {
+                                ui.text("ok");
+                            }
+                        }
+                    }
+                });
+        }
+
+        if let Some(demo_board) = &state.worker.demo_board {
+            ui.window("demo board")
+                .size([300.0, 250.0], Condition::FirstUseEver)
+                .position([ui.io().display_size[0] - 350.0, ui.io().display_size[1] - 300.0], Condition::FirstUseEver)
+                .build(|| {
+                    let mut io = demo_board.lock().unwrap();
+
+                    ui.text("inputs:");
+                    for (name, value) in io.inputs.iter_mut() {
+                        ui.slider(name, 0, 4095, value);
+                    }
+
+                    ui.separator();
+                    for (name, value) in io.outputs.iter() {
+                        ui.text(format!("{}: {}", name, value));
+                    }
+                    ui.text(format!("leds: {}", if io.leds { "on" } else { "off" }));
+                });
+        }
     });
 }
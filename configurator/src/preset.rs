@@ -0,0 +1,168 @@
+use std::io::Write;
+use std::path::Path;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use crate::serial::Settings;
+
+// Version of the preset format, stored in the files next to the fields.
+// Files from before there was one are version 0.
+pub const VERSION: u64 = 1;
+
+// Each takes a preset of its version to the next one, MIGRATIONS[0] turns
+// version 0 into 1
+const MIGRATIONS: [fn(&mut Map<String, Value>); VERSION as usize] = [from_v0];
+
+// A preset file as it is written
+#[derive(Serialize, JsonSchema)]
+#[schemars(title = "ftSwarm configurator preset")]
+struct Stored {
+    version: u64,
+    #[serde(flatten)]
+    settings: Settings,
+}
+
+// Version 0 has the same fields, only without the version
+fn from_v0(_fields: &mut Map<String, Value>) {}
+
+// Reads a preset of any version. Fields that are missing or can't be read
+// get their defaults, each with a warning, so an old or hand-edited file
+// still loads.
+pub fn load(path: &Path) -> Result<(Settings, Vec<String>), String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    let value = serde_json::from_slice(&data).map_err(|e| e.to_string())?;
+    parse(value)
+}
+
+pub fn parse(value: Value) -> Result<(Settings, Vec<String>), String> {
+    let Value::Object(mut fields) = value else { return Err("not a JSON object".to_string()) };
+    let mut warnings = vec![];
+
+    let version = match fields.remove("version") {
+        Some(version) => version.as_u64().ok_or_else(|| format!("version {} isn't a number", version))?,
+        None => 0,
+    };
+    if version > VERSION {
+        warnings.push(format!("written for preset version {}, this configurator knows up to {}", version, VERSION));
+    }
+    for migrate in MIGRATIONS.iter().skip(version as usize) {
+        migrate(&mut fields);
+    }
+
+    // field by field, a bad value only loses itself
+    let defaults = match serde_json::to_value(Settings::default()) {
+        Ok(Value::Object(defaults)) => defaults,
+        _ => Map::new(),
+    };
+    let mut merged = defaults.clone();
+    for (name, default) in defaults.iter() {
+        let Some(value) = fields.remove(name) else {
+            warnings.push(format!("no {}, using {}", name, default));
+            continue;
+        };
+
+        let mut tried = merged.clone();
+        tried.insert(name.clone(), value.clone());
+        if serde_json::from_value::<Settings>(Value::Object(tried)).is_ok() {
+            merged.insert(name.clone(), value);
+        } else {
+            warnings.push(format!("{} can't be {}, using {}", name, value, default));
+        }
+    }
+    for name in fields.keys() {
+        warnings.push(format!("unknown field {} is ignored", name));
+    }
+
+    let settings = serde_json::from_value(Value::Object(merged)).map_err(|e| e.to_string())?;
+    Ok((settings, warnings))
+}
+
+// The preset as it is written, with the version
+pub fn to_json(settings: &Settings) -> Value {
+    json!(Stored { version: VERSION, settings: settings.clone() })
+}
+
+pub fn save(path: &Path, settings: &Settings) -> std::io::Result<()> {
+    let data = serde_json::to_string(&to_json(settings))?;
+    std::fs::File::create(path)?.write_all(data.as_bytes())
+}
+
+// JSON Schema of the files save() writes
+pub fn schema() -> Value {
+    let mut schema = json!(schemars::schema_for!(Stored));
+    schema["properties"]["version"] = json!({ "const": VERSION });
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Settings {
+        Settings {
+            hostname: "robot1".to_string(),
+            rgb_led_num: 3,
+            input_ports: vec!["start".to_string(), String::new()],
+            servo_port: "arm".to_string(),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn published_schema_is_current() {
+        let published: Value = serde_json::from_str(include_str!("../resources/preset.schema.json")).unwrap();
+        assert_eq!(published, schema(), "run `configurator schema > resources/preset.schema.json`");
+    }
+
+    #[test]
+    fn round_trip() {
+        let settings = example();
+        let stored = to_json(&settings);
+        assert_eq!(stored["version"], json!(VERSION));
+
+        let (loaded, warnings) = parse(stored).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(json!(loaded), json!(settings));
+    }
+
+    #[test]
+    fn version_0_loads() {
+        let mut stored = to_json(&example());
+        stored.as_object_mut().unwrap().remove("version");
+
+        let (loaded, warnings) = parse(stored).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(json!(loaded), json!(example()));
+    }
+
+    #[test]
+    fn bad_field_keeps_the_others() {
+        let mut stored = to_json(&example());
+        stored["rgb_led_num"] = json!("three");
+        stored.as_object_mut().unwrap().remove("servo_port");
+        stored["colour"] = json!("red");
+
+        let (loaded, warnings) = parse(stored).unwrap();
+        assert_eq!(json!(loaded), json!(Settings { rgb_led_num: 2, servo_port: String::new(), ..example() }));
+        assert_eq!(
+            warnings,
+            ["rgb_led_num can't be \"three\", using 2", "no servo_port, using \"\"", "unknown field colour is ignored"]
+        );
+    }
+
+    #[test]
+    fn future_version_warns() {
+        let mut stored = to_json(&example());
+        stored["version"] = json!(VERSION + 1);
+
+        let (loaded, warnings) = parse(stored).unwrap();
+        assert_eq!(json!(loaded), json!(example()));
+        assert_eq!(warnings, [format!("written for preset version {}, this configurator knows up to {}", VERSION + 1, VERSION)]);
+    }
+
+    #[test]
+    fn not_a_preset() {
+        assert!(parse(json!([1, 2])).is_err());
+        assert!(parse(json!({ "version": "one" })).is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use crate::json_store;
+use crate::serial::Profile;
+
+#[derive(Default, Serialize, Deserialize)]
+struct Stored {
+    profiles: Vec<Profile>,
+    // port, see port_key() in main, -> profile name
+    ports: BTreeMap<String, String>,
+}
+
+// Connection profiles and the one chosen for each port, kept in a file of
+// their own
+pub struct Profiles {
+    path: PathBuf,
+    stored: Stored,
+}
+
+impl Profiles {
+    // A missing or unreadable file gives just the default profile
+    pub fn load(path: &Path) -> Profiles {
+        let mut stored: Stored = json_store::load(path);
+
+        if !stored.profiles.iter().any(|x| x.name == Profile::default().name) {
+            stored.profiles.insert(0, Profile::default());
+        }
+
+        Profiles { path: path.to_path_buf(), stored }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.stored.profiles.iter().map(|x| x.name.clone()).collect()
+    }
+
+    // The default profile if there is none of that name
+    pub fn get(&self, name: &str) -> Profile {
+        self.stored.profiles.iter().find(|x| x.name == name).cloned().unwrap_or_default()
+    }
+
+    pub fn name_for_port(&self, port: &str) -> String {
+        self.stored.ports.get(port).cloned().unwrap_or_else(|| Profile::default().name)
+    }
+
+    pub fn for_port(&self, port: &str) -> Profile {
+        self.get(&self.name_for_port(port))
+    }
+
+    pub fn assign(&mut self, port: &str, name: &str) -> std::io::Result<()> {
+        self.stored.ports.insert(port.to_string(), name.to_string());
+        self.save()
+    }
+
+    // Adds a profile or replaces the one of the same name
+    pub fn set(&mut self, profile: Profile) -> std::io::Result<()> {
+        match self.stored.profiles.iter_mut().find(|x| x.name == profile.name) {
+            Some(entry) => *entry = profile,
+            None => self.stored.profiles.push(profile),
+        }
+        self.save()
+    }
+
+    // Ports that used the profile go back to the default one, which can't
+    // be removed
+    pub fn remove(&mut self, name: &str) -> std::io::Result<()> {
+        if name == Profile::default().name {
+            return Ok(());
+        }
+        self.stored.profiles.retain(|x| x.name != name);
+        self.stored.ports.retain(|_, x| x != name);
+        self.save()
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        json_store::save(&self.path, &self.stored)
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use serde::Deserialize;
+use serde_json::Value;
+use crate::serial::{Settings, UsbDevice};
+use crate::template;
+
+// Columns of a CSV manifest that fill placeholders
+const VAR_PREFIX: &str = "var.";
+
+// A TOML file to set up many boards the same way, e.g.
+//
+//   preset = "event"
+//
+//   [[board]]
+//   port = "/dev/ttyUSB0"
+//   hostname = "robot1"
+//
+//   [[board]]
+//   serial = "A50285BI"
+//   hostname = "robot2"
+//   input_ports = ["start", "stop", "", ""]
+//   vars = { station = "Bergstation" }
+//
+// or a CSV file with a port or serial column, optionally a profile column,
+// and columns named after preset fields or, for the placeholders of a
+// template preset, var.<name>:
+//
+//   port,hostname,input_ports,var.station
+//   /dev/ttyUSB0,robot1,"[""start"", ""stop""]",Bergstation
+//
+// Fields that aren't text are written as in TOML. An empty cell keeps the
+// field of the base preset.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    // base preset, a name in the config directory or a path
+    #[serde(default)]
+    pub preset: String,
+    // connection profile for boards without one of their own
+    pub profile: Option<String>,
+    #[serde(default, rename = "board")]
+    pub boards: Vec<Board>,
+}
+
+#[derive(Deserialize)]
+pub struct Board {
+    pub port: Option<String>,
+    // serial number of the USB adapter, for ports that move around
+    pub serial: Option<String>,
+    pub profile: Option<String>,
+    // values for the placeholders of a template preset
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    // preset fields that differ from the base preset
+    #[serde(flatten)]
+    pub settings: BTreeMap<String, toml::Value>,
+}
+
+impl Manifest {
+    // `preset` replaces the base preset of the file
+    pub fn load(path: &Path, preset: Option<String>) -> Result<Manifest, String> {
+        let mut manifest = if path.extension().is_some_and(|x| x == "csv") {
+            Manifest::read_csv(path)?
+        } else {
+            let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+            toml::from_str(&text).map_err(|e| e.to_string())?
+        };
+
+        if let Some(preset) = preset {
+            manifest.preset = preset;
+        }
+        if manifest.preset.is_empty() {
+            return Err("no base preset, name one in the manifest or with --preset".to_string());
+        }
+
+        let fields = settings_json(&Settings::default());
+        for (i, board) in manifest.boards.iter().enumerate() {
+            if board.port.is_some() == board.serial.is_some() {
+                return Err(format!("board {} needs either a port or a serial number", i + 1));
+            }
+            if let Some(field) = board.settings.keys().find(|x| !fields.contains_key(*x)) {
+                return Err(format!("board {}: no preset field {}", i + 1, field));
+            }
+        }
+        Ok(manifest)
+    }
+
+    fn read_csv(path: &Path) -> Result<Manifest, String> {
+        let mut reader = csv::Reader::from_path(path).map_err(|e| e.to_string())?;
+        let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+
+        let fields = settings_json(&Settings::default());
+        for column in headers.iter().map(str::trim) {
+            if !matches!(column, "port" | "serial" | "profile") && !column.starts_with(VAR_PREFIX) && !fields.contains_key(column) {
+                return Err(format!("column {} is no preset field, placeholders go in a column {}{}", column, VAR_PREFIX, column));
+            }
+        }
+
+        let mut boards = vec![];
+        for (i, record) in reader.records().enumerate() {
+            let record = record.map_err(|e| e.to_string())?;
+            let mut board = Board { port: None, serial: None, profile: None, vars: BTreeMap::new(), settings: BTreeMap::new() };
+            for (column, value) in headers.iter().zip(record.iter()) {
+                let value = value.trim().to_string();
+                match column.trim() {
+                    "port" if !value.is_empty() => board.port = Some(value),
+                    "serial" if !value.is_empty() => board.serial = Some(value),
+                    "profile" if !value.is_empty() => board.profile = Some(value),
+                    "port" | "serial" | "profile" => {}
+                    name => match name.strip_prefix(VAR_PREFIX) {
+                        Some(var) => {
+                            board.vars.insert(var.to_string(), value);
+                        }
+                        None if value.is_empty() => {}
+                        None => {
+                            let value = cell_value(&fields[name], &value).map_err(|e| format!("board {}: {}: {}", i + 1, name, e))?;
+                            board.settings.insert(name.to_string(), value);
+                        }
+                    },
+                }
+            }
+            boards.push(board);
+        }
+
+        Ok(Manifest { preset: String::new(), profile: None, boards })
+    }
+}
+
+impl Board {
+    // How the manifest names the board
+    pub fn name(&self) -> String {
+        match (&self.port, &self.serial) {
+            (Some(port), _) => port.clone(),
+            (None, Some(serial)) => format!("serial {}", serial),
+            (None, None) => String::new(),
+        }
+    }
+
+    // The port the board is on now
+    pub fn find_port(&self, devices: &BTreeMap<String, UsbDevice>) -> Result<String, String> {
+        if let Some(port) = &self.port {
+            return Ok(port.clone());
+        }
+
+        let serial = self.serial.as_deref();
+        devices.iter()
+            .find(|(_, device)| device.serial.as_deref() == serial)
+            .map(|(port, _)| port.clone())
+            .ok_or_else(|| format!("no USB adapter with {}", self.name()))
+    }
+
+    // The base preset with the fields of the board, placeholders filled in.
+    // `index` counts the boards of the manifest from 1.
+    pub fn settings(&self, base: &Settings, index: usize, port: Option<&str>, devices: &BTreeMap<String, UsbDevice>) -> Result<Settings, String> {
+        let mut settings = settings_json(base);
+        for (field, value) in self.settings.iter() {
+            settings.insert(field.clone(), serde_json::to_value(value).map_err(|e| e.to_string())?);
+        }
+        let settings = serde_json::from_value(Value::Object(settings)).map_err(|e| format!("{}: {}", self.name(), e))?;
+
+        let mut values = port.map(|x| template::board_values(x, devices.get(x))).unwrap_or_default();
+        if let Some(serial) = &self.serial {
+            values.insert("serial".to_string(), serial.clone());
+        }
+        values.insert("index".to_string(), index.to_string());
+        values.extend(self.vars.clone());
+        template::fill(&settings, &values).map_err(|e| format!("{}: {}", self.name(), e))
+    }
+}
+
+// A CSV cell as the value of a preset field like `default`
+fn cell_value(default: &Value, text: &str) -> Result<toml::Value, String> {
+    if default.is_string() {
+        return Ok(toml::Value::String(text.to_string()));
+    }
+    toml::from_str::<BTreeMap<String, toml::Value>>(&format!("value = {}", text))
+        .ok()
+        .and_then(|mut x| x.remove("value"))
+        .ok_or_else(|| format!("{} is no TOML value", text))
+}
+
+fn settings_json(settings: &Settings) -> serde_json::Map<String, Value> {
+    match serde_json::to_value(settings) {
+        Ok(Value::Object(fields)) => fields,
+        _ => serde_json::Map::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_csv(name: &str, text: &str) -> Result<Manifest, String> {
+        let path = std::env::temp_dir().join(format!("swarm-{}-{}.csv", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let manifest = Manifest::load(&path, Some("event".to_string()));
+        std::fs::remove_file(&path).unwrap();
+        manifest
+    }
+
+    #[test]
+    fn csv_fields_and_placeholders() {
+        let manifest = load_csv(
+            "fields",
+            "port,serial,hostname,rgb_led_num,input_ports,var.station\n\
+             /dev/ttyUSB0,,robot${index},3,\"[\"\"start\"\", \"\"stop\"\"]\",Bergstation\n\
+             ,A50285BI,,,,Talstation\n",
+        )
+        .unwrap();
+        assert_eq!(manifest.preset, "event");
+
+        let base = Settings { ssid: "${station}".to_string(), ..Settings::default() };
+        let devices = BTreeMap::new();
+        let first = manifest.boards[0].settings(&base, 1, Some("/dev/ttyUSB0"), &devices).unwrap();
+        assert_eq!(first.hostname, "robot1");
+        assert_eq!(first.rgb_led_num, 3);
+        assert_eq!(first.input_ports, ["start", "stop"]);
+        assert_eq!(first.ssid, "Bergstation");
+
+        // empty cells keep the base preset
+        let second = &manifest.boards[1];
+        assert_eq!(second.serial.as_deref(), Some("A50285BI"));
+        assert!(second.settings.is_empty());
+        let second = second.settings(&base, 2, None, &devices).unwrap();
+        assert_eq!(second.hostname, base.hostname);
+        assert_eq!(second.ssid, "Talstation");
+    }
+
+    #[test]
+    fn csv_column_must_be_a_field_or_placeholder() {
+        let error = load_csv("column", "port,station\n/dev/ttyUSB0,Bergstation\n").err().unwrap();
+        assert_eq!(error, "column station is no preset field, placeholders go in a column var.station");
+
+        let error = load_csv("cell", "port,rgb_led_num\n/dev/ttyUSB0,three\n").err().unwrap();
+        assert_eq!(error, "board 1: rgb_led_num: three is no TOML value");
+    }
+}
@@ -0,0 +1,58 @@
+use std::fmt;
+use std::io;
+
+// Everything that can go wrong talking to a board. `step` names the part of
+// the operation that failed, e.g. "set SSID".
+#[derive(Debug)]
+pub enum SerialError {
+    NotConnected,
+    Io { step: String, error: io::Error },
+    // the board stopped answering
+    Timeout { step: String, expected: Vec<String>, received: String },
+    Closed { step: String },
+    // the board answered, but not the way the firmware is known to
+    Protocol { step: String, reason: String },
+    // the board would not accept the value
+    Rejected { step: String, reason: String },
+    Cancelled { step: String },
+}
+
+impl SerialError {
+    // The connection can't be used anymore, e.g. the cable was pulled
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            SerialError::Closed { .. } => true,
+            SerialError::Io { error, .. } => !matches!(error.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for SerialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerialError::NotConnected => write!(f, "not connected"),
+            SerialError::Io { step, error } => write!(f, "step \"{}\" failed: {}", step, error),
+            SerialError::Timeout { step, expected, received } => write!(
+                f,
+                "step \"{}\" stalled waiting for {}, last received: {:?}",
+                step,
+                expected.iter().map(|x| format!("{:?}", x)).collect::<Vec<_>>().join(" or "),
+                received
+            ),
+            SerialError::Closed { step } => write!(f, "step \"{}\" failed: connection closed", step),
+            SerialError::Protocol { step, reason } => write!(f, "step \"{}\" got an unexpected reply: {}", step, reason),
+            SerialError::Rejected { step, reason } => write!(f, "step \"{}\" failed: {}", step, reason),
+            SerialError::Cancelled { step } => write!(f, "cancelled at step \"{}\"", step),
+        }
+    }
+}
+
+impl std::error::Error for SerialError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SerialError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use super::error::SerialError;
+use super::transport::Transport;
+
+// Timeout for a menu to answer a keystroke with its next prompt
+pub const PROMPT_TIMEOUT: Duration = Duration::from_secs(3);
+// Timeout for the board to reboot and print the sketch's ">>>" banner
+pub const BOOT_TIMEOUT: Duration = Duration::from_secs(20);
+// Timeout for the line to go quiet, see discard()
+pub const QUIET_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub enum Output<'a> {
+    // raw bytes received from the board
+    Received(&'a [u8]),
+    // progress message of the running operation
+    Progress(&'a str),
+    // share of the running operation that is done, 0 to 1
+    Fraction(f32),
+}
+
+// Drives the text menus of a board: send keystrokes and wait for the prompts
+// that answer them.
+pub struct Expect<'a> {
+    transport: &'a mut dyn Transport,
+    output: &'a mut dyn FnMut(Output),
+    buffer: String,
+    // the last bytes received, whether consumed or not
+    tail: String,
+    // start of a character whose other bytes haven't arrived yet
+    pending: Vec<u8>,
+    step: String,
+    cancel: Arc<AtomicBool>,
+    // units of work done and planned, see plan()
+    done: usize,
+    total: usize,
+    // reported share, never goes back when the plan grows
+    fraction: f32,
+}
+
+// Bytes of output kept in `tail`
+const TAIL: usize = 256;
+
+impl<'a> Expect<'a> {
+    pub fn new(transport: &'a mut dyn Transport, output: &'a mut dyn FnMut(Output)) -> Expect<'a> {
+        Expect {
+            transport,
+            output,
+            buffer: String::new(),
+            tail: String::new(),
+            pending: Vec::new(),
+            step: String::new(),
+            cancel: Arc::new(AtomicBool::new(false)),
+            done: 0,
+            total: 0,
+            fraction: 0.0,
+        }
+    }
+
+    // Waits fail with `SerialError::Cancelled` once `cancel` is set
+    pub fn with_cancel(mut self, cancel: Arc<AtomicBool>) -> Expect<'a> {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn current_step(&self) -> &str {
+        &self.step
+    }
+
+    // The last output of the board, e.g. to tell which prompt it waits at
+    pub fn tail(&self) -> &str {
+        &self.tail
+    }
+
+    fn received(&mut self, data: &[u8]) -> String {
+        (self.output)(Output::Received(data));
+        self.pending.extend_from_slice(data);
+        let data = self.decode();
+
+        self.tail.push_str(&data);
+        if self.tail.len() > TAIL {
+            let mut start = self.tail.len() - TAIL;
+            while !self.tail.is_char_boundary(start) {
+                start += 1;
+            }
+            self.tail.drain(..start);
+        }
+        data
+    }
+
+    // Text of the pending bytes, keeping a character cut off at the end for
+    // the next read
+    fn decode(&mut self) -> String {
+        let mut text = String::new();
+        loop {
+            match std::str::from_utf8(&self.pending) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    self.pending.clear();
+                    return text;
+                }
+                Err(e) => {
+                    let end = e.valid_up_to();
+                    text.push_str(std::str::from_utf8(&self.pending[..end]).unwrap_or_default());
+                    match e.error_len() {
+                        Some(invalid) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            self.pending.drain(..end + invalid);
+                        }
+                        None => {
+                            self.pending.drain(..end);
+                            return text;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_cancel(&self) -> Result<(), SerialError> {
+        if self.cancel.load(Ordering::Relaxed) {
+            return Err(SerialError::Cancelled { step: self.step.clone() });
+        }
+        Ok(())
+    }
+
+    // Names the step reported when sending or waiting fails
+    pub fn step(&mut self, step: &str) {
+        self.step = step.to_string();
+    }
+
+    pub fn progress(&mut self, message: &str) {
+        (self.output)(Output::Progress(message));
+    }
+
+    // Sets the units of work the operation takes in total, it may be
+    // corrected once more is known
+    pub fn plan(&mut self, total: usize) {
+        self.total = total;
+    }
+
+    // One unit of work is done
+    pub fn advance(&mut self) {
+        self.done += 1;
+        if self.total > 0 {
+            let fraction = self.done.min(self.total) as f32 / self.total as f32;
+            if fraction > self.fraction {
+                self.fraction = fraction;
+                (self.output)(Output::Fraction(fraction));
+            }
+        }
+    }
+
+    // Sends a line, terminated by a single "\n" as the menus treat "\r" and
+    // "\n" as two separate inputs
+    pub fn send(&mut self, line: &str) -> Result<(), SerialError> {
+        let data = format!("{}\n", line);
+        self.transport
+            .write_all(data.as_bytes())
+            .and_then(|_| self.transport.flush())
+            .map_err(|error| SerialError::Io { step: self.step.clone(), error })
+    }
+
+    // Sends bytes as they are, e.g. an escape to leave a prompt
+    pub fn send_raw(&mut self, data: &[u8]) -> Result<(), SerialError> {
+        self.transport
+            .write_all(data)
+            .and_then(|_| self.transport.flush())
+            .map_err(|error| SerialError::Io { step: self.step.clone(), error })
+    }
+
+    // Drops everything received so far and whatever arrives until the line
+    // has been quiet for `quiet`. A board that keeps talking, e.g. in a boot
+    // loop, fails after QUIET_TIMEOUT.
+    pub fn discard(&mut self, quiet: Duration) -> Result<(), SerialError> {
+        let deadline = Instant::now() + QUIET_TIMEOUT;
+        let mut buffer = [0; 256];
+        self.buffer.clear();
+
+        let _ = self.transport.set_timeout(quiet);
+        loop {
+            self.check_cancel()?;
+            if Instant::now() >= deadline {
+                let tail: String = self.tail.chars().rev().take(80).collect::<Vec<_>>().into_iter().rev().collect();
+                return Err(SerialError::Timeout {
+                    step: self.step.clone(),
+                    expected: vec![format!("{}ms without output", quiet.as_millis())],
+                    received: tail,
+                });
+            }
+            match self.transport.read(&mut buffer) {
+                Ok(0) => return Err(SerialError::Closed { step: self.step.clone() }),
+                Ok(n) => {
+                    self.received(&buffer[..n]);
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(error) => return Err(SerialError::Io { step: self.step.clone(), error }),
+            }
+        }
+    }
+
+    // Waits for `pattern` and returns everything received up to and
+    // including it. The text is consumed.
+    pub fn expect(&mut self, pattern: &str, timeout: Duration) -> Result<String, SerialError> {
+        self.expect_any(&[pattern], timeout).map(|(_, text)| text)
+    }
+
+    // Waits for the first of `patterns` to show up
+    pub fn expect_any(&mut self, patterns: &[&str], timeout: Duration) -> Result<(usize, String), SerialError> {
+        let deadline = Instant::now() + timeout;
+        let mut buffer = [0; 256];
+
+        loop {
+            self.check_cancel()?;
+            let found = patterns
+                .iter()
+                .enumerate()
+                .filter_map(|(i, pattern)| self.buffer.find(pattern).map(|at| (at + pattern.len(), i)))
+                .min();
+            if let Some((end, i)) = found {
+                let text: String = self.buffer.drain(..end).collect();
+                return Ok((i, text));
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                let tail: String = self.buffer.chars().rev().take(80).collect::<Vec<_>>().into_iter().rev().collect();
+                return Err(SerialError::Timeout {
+                    step: self.step.clone(),
+                    expected: patterns.iter().map(|x| x.to_string()).collect(),
+                    received: tail,
+                });
+            }
+
+            let _ = self.transport.set_timeout((deadline - now).min(Duration::from_millis(100)));
+            match self.transport.read(&mut buffer) {
+                Ok(0) => return Err(SerialError::Closed { step: self.step.clone() }),
+                Ok(n) => {
+                    let data = self.received(&buffer[..n]);
+                    self.buffer.push_str(&data);
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(error) => return Err(SerialError::Io { step: self.step.clone(), error }),
+            }
+        }
+    }
+
+    // Sends a line and waits for the prompt answering it
+    pub fn send_expect(&mut self, line: &str, prompt: &str, timeout: Duration) -> Result<String, SerialError> {
+        self.send(line)?;
+        self.expect(prompt, timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::transport::MemoryTransport;
+
+    #[test]
+    fn characters_split_across_reads() {
+        let (mut host, mut board) = MemoryTransport::pair();
+        let mut output = |_: Output| {};
+        let mut expect = Expect::new(&mut host, &mut output);
+        let text = "Größe €>".as_bytes();
+
+        board.write_all(&text[..3]).unwrap();
+        assert!(expect.expect(">", Duration::from_millis(50)).is_err());
+        board.write_all(&text[3..text.len() - 2]).unwrap();
+        assert!(expect.expect(">", Duration::from_millis(50)).is_err());
+        board.write_all(&text[text.len() - 2..]).unwrap();
+        assert_eq!(expect.expect(">", Duration::from_millis(50)).unwrap(), "Größe €>");
+        assert_eq!(expect.tail(), "Größe €>");
+    }
+
+    // Writes a line every 50ms until the host's end is dropped
+    fn chatty(mut board: MemoryTransport) {
+        std::thread::spawn(move || {
+            while board.write_all(b"!light 512\r\n").is_ok() {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        });
+    }
+
+    #[test]
+    fn discard_gives_up_on_a_board_that_keeps_talking() {
+        let (mut host, board) = MemoryTransport::pair();
+        chatty(board);
+        let mut output = |_: Output| {};
+        let mut expect = Expect::new(&mut host, &mut output);
+        expect.step("wait for sketch");
+
+        let started = Instant::now();
+        match expect.discard(Duration::from_millis(200)) {
+            Err(SerialError::Timeout { step, received, .. }) => {
+                assert_eq!(step, "wait for sketch");
+                assert!(received.ends_with("!light 512\r\n"), "{:?}", received);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(started.elapsed() < QUIET_TIMEOUT + Duration::from_secs(1));
+    }
+
+    #[test]
+    fn cancel_stops_a_wait() {
+        let (mut host, board) = MemoryTransport::pair();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut output = |_: Output| {};
+        let mut expect = Expect::new(&mut host, &mut output).with_cancel(cancel.clone());
+        expect.step("open settings prompt");
+
+        let canceller = cancel.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            canceller.store(true, Ordering::SeqCst);
+        });
+        let started = Instant::now();
+        assert!(matches!(expect.expect("main>", BOOT_TIMEOUT), Err(SerialError::Cancelled { step }) if step == "open settings prompt"));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // and one for a quiet line
+        chatty(board);
+        assert!(matches!(expect.discard(Duration::from_millis(200)), Err(SerialError::Cancelled { .. })));
+    }
+
+    #[test]
+    fn invalid_bytes_are_replaced() {
+        let (mut host, mut board) = MemoryTransport::pair();
+        let mut output = |_: Output| {};
+        let mut expect = Expect::new(&mut host, &mut output);
+
+        board.write_all(b"a\xffb\xc3(>").unwrap();
+        assert_eq!(expect.expect(">", Duration::from_millis(50)).unwrap(), "a\u{fffd}b\u{fffd}(>");
+    }
+}
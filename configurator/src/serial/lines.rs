@@ -0,0 +1,146 @@
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    // received from the board
+    Rx,
+    // sent to the board
+    Tx,
+    // message of the configurator itself
+    Local,
+}
+
+#[derive(Debug, Clone)]
+pub struct Line {
+    // when the first byte of the line arrived
+    pub time: SystemTime,
+    pub direction: Direction,
+    pub text: String,
+    // false for the unterminated tail, e.g. a prompt waiting for input
+    pub complete: bool,
+}
+
+impl Line {
+    pub fn new(direction: Direction, text: String) -> Line {
+        Line { time: SystemTime::now(), direction, text, complete: true }
+    }
+}
+
+// Cuts received bytes into lines. CR, LF and CRLF all end a line, bytes
+// that aren't UTF-8 are replaced once the line is complete, so characters
+// split across reads survive.
+#[derive(Default)]
+pub struct LineAssembler {
+    partial: Vec<u8>,
+    started: Option<SystemTime>,
+    // the last line ended with CR, a following LF belongs to it
+    after_cr: bool,
+}
+
+impl LineAssembler {
+    // Returns the lines completed by `data`
+    pub fn push(&mut self, data: &[u8]) -> Vec<Line> {
+        self.push_at(data, SystemTime::now())
+    }
+
+    // For bytes that arrived earlier, e.g. in a recording
+    pub fn push_at(&mut self, data: &[u8], time: SystemTime) -> Vec<Line> {
+        let mut lines = vec![];
+
+        for &byte in data {
+            if byte == b'\n' && self.after_cr {
+                self.after_cr = false;
+                continue;
+            }
+            self.after_cr = byte == b'\r';
+
+            if byte == b'\n' || byte == b'\r' {
+                lines.push(self.take());
+            } else {
+                self.started.get_or_insert(time);
+                self.partial.push(byte);
+            }
+        }
+
+        lines
+    }
+
+    // Text received after the last line ending
+    pub fn partial(&self) -> Option<Line> {
+        if self.partial.is_empty() {
+            return None;
+        }
+
+        Some(Line {
+            time: self.started.unwrap_or_else(SystemTime::now),
+            direction: Direction::Rx,
+            text: String::from_utf8_lossy(&self.partial).to_string(),
+            complete: false,
+        })
+    }
+
+    pub fn clear(&mut self) {
+        *self = LineAssembler::default();
+    }
+
+    fn take(&mut self) -> Line {
+        let text = String::from_utf8_lossy(&self.partial).to_string();
+        self.partial.clear();
+
+        Line {
+            time: self.started.take().unwrap_or_else(SystemTime::now),
+            direction: Direction::Rx,
+            text,
+            complete: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(lines: Vec<Line>) -> Vec<String> {
+        lines.into_iter().map(|x| x.text).collect()
+    }
+
+    #[test]
+    fn line_endings() {
+        let mut lines = LineAssembler::default();
+        assert_eq!(texts(lines.push(b"cr\rlf\ncrlf\r\n\n\r")), ["cr", "lf", "crlf", "", ""]);
+        assert!(lines.partial().is_none());
+    }
+
+    #[test]
+    fn crlf_split_across_chunks() {
+        let mut lines = LineAssembler::default();
+        assert_eq!(texts(lines.push(b"one\r")), ["one"]);
+        assert_eq!(texts(lines.push(b"\ntwo\r")), ["two"]);
+        assert_eq!(texts(lines.push(b"\n")), Vec::<String>::new());
+        assert_eq!(texts(lines.push(b"\n")), [""]);
+    }
+
+    #[test]
+    fn character_split_across_chunks() {
+        let mut lines = LineAssembler::default();
+        let text = "Größe €".as_bytes();
+        // ö and € end up in different chunks, each cut in the middle
+        assert!(lines.push(&text[..3]).is_empty());
+        assert_eq!(lines.partial().unwrap().text, "Gr\u{fffd}");
+        assert!(lines.push(&text[3..text.len() - 1]).is_empty());
+        let mut last = text[text.len() - 1..].to_vec();
+        last.push(b'\n');
+        assert_eq!(texts(lines.push(&last)), ["Größe €"]);
+    }
+
+    #[test]
+    fn partial_stays_until_the_line_ends() {
+        let mut lines = LineAssembler::default();
+        assert!(lines.push(b"ftSwarm> ").is_empty());
+        let partial = lines.partial().unwrap();
+        assert_eq!(partial.text, "ftSwarm> ");
+        assert!(!partial.complete);
+        assert_eq!(texts(lines.push(b"1\r\n")), ["ftSwarm> 1"]);
+        assert!(lines.partial().is_none());
+    }
+}
@@ -0,0 +1,570 @@
+use std::time::Duration;
+use super::error::SerialError;
+use super::expect::{Expect, BOOT_TIMEOUT, PROMPT_TIMEOUT};
+use super::{Settings, Type};
+
+// Prompts printed by ftSwarm.setup()
+const MAIN: &str = "main>";
+const WIFI: &str = "wifi>";
+const WEB_SERVER: &str = "web server>";
+const SWARM: &str = "swarm>";
+const ALIAS: &str = "alias>";
+const YES_NO: &str = "(Y/N)?";
+const READY: &str = ">>>";
+
+// Creating or joining a swarm pairs with the other controllers first
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+const MAXIDENTIFIER: usize = 32;
+// see diff()
+const PIN_ONLY: &str = "the swarm pin can only change together with the swarm name";
+
+// One line of the alias menu, e.g. "( 2) A1   - taster"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AliasEntry {
+    pub item: u16,
+    // port name, "hostname" for the controller itself
+    pub name: String,
+    pub alias: String,
+}
+
+pub fn parse_alias_table(screen: &str) -> Vec<AliasEntry> {
+    let mut entries = vec![];
+
+    for line in screen.lines() {
+        let Some(rest) = line.trim_start().strip_prefix('(') else { continue };
+        let Some((item, rest)) = rest.split_once(')') else { continue };
+        let Ok(item) = item.trim().parse::<u16>() else { continue };
+        // the alias is padded with spaces, or missing entirely
+        let Some((name, alias)) = rest.split_once(" -") else { continue };
+
+        let name = name.trim();
+        let name = if name.starts_with("hostname ") { "hostname" } else { name };
+        entries.push(AliasEntry { item, name: name.to_string(), alias: alias.trim().to_string() });
+    }
+
+    entries
+}
+
+// Port names and aliases as the alias menu should list them
+pub fn alias_targets(settings: &Settings) -> Vec<(String, String)> {
+    let inputs = if settings.swarm_type == Type::RS485 { 6 } else { 4 };
+    let mut targets = vec![("hostname".to_string(), settings.hostname.clone())];
+
+    for i in 0..inputs {
+        targets.push((format!("A{}", i + 1), settings.input_ports.get(i).cloned().unwrap_or_default()));
+    }
+    for i in 0..2 {
+        targets.push((format!("M{}", i + 1), settings.output_ports.get(i).cloned().unwrap_or_default()));
+    }
+    for i in 0..settings.rgb_led_num as usize {
+        targets.push((format!("LED{}", i + 1), settings.led_ports.get(i).cloned().unwrap_or_default()));
+    }
+    targets.push(("SERVO".to_string(), settings.servo_port.clone()));
+
+    targets
+}
+
+fn rejected(step: &str, reason: String) -> SerialError {
+    SerialError::Rejected { step: step.to_string(), reason }
+}
+
+fn unexpected(step: &str, reason: &str) -> SerialError {
+    SerialError::Protocol { step: step.to_string(), reason: reason.to_string() }
+}
+
+// The menus silently drop characters they don't accept and loop on values
+// out of range, so anything the board would not take is refused up front.
+pub fn validate(settings: &Settings) -> Result<(), SerialError> {
+    let step = "validate settings";
+    let printable = |x: &str| x.bytes().all(|c| (32..=126).contains(&c));
+
+    if !printable(&settings.ssid) || settings.ssid.len() > 63 {
+        return Err(rejected(step, "SSID must be at most 63 printable characters".to_string()));
+    }
+    if !printable(&settings.password) || settings.password.len() > 63 {
+        return Err(rejected(step, "password must be at most 63 printable characters".to_string()));
+    }
+    if !(2..=18).contains(&settings.rgb_led_num) {
+        return Err(rejected(step, "rgb led num must be between 2 and 18".to_string()));
+    }
+    if !printable(&settings.swarm_name) || settings.swarm_name.len() < 5 || settings.swarm_name.len() >= MAXIDENTIFIER {
+        return Err(rejected(step, "swarm name must have 5 to 31 printable characters".to_string()));
+    }
+    if !matches!(settings.swarm_pin.parse::<u16>(), Ok(1..=9999)) {
+        return Err(rejected(step, "swarm pin must be a number between 1 and 9999".to_string()));
+    }
+    for (name, alias) in alias_targets(settings) {
+        if !alias.bytes().all(|c| c.is_ascii_alphanumeric()) || alias.len() >= MAXIDENTIFIER {
+            return Err(rejected(step, format!("alias of {} must have at most 31 letters or digits", name)));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn reset(expect: &mut Expect) -> Result<(), SerialError> {
+    expect.step("reset");
+    // a ">>>" still in the buffer would be taken for the reboot
+    expect.discard(Duration::from_millis(200))?;
+    expect.send("res")?;
+    expect.expect(READY, BOOT_TIMEOUT)?;
+    Ok(())
+}
+
+fn open_setup(expect: &mut Expect) -> Result<(), SerialError> {
+    expect.step("open settings prompt");
+    expect.send_expect("stp", MAIN, PROMPT_TIMEOUT)?;
+    Ok(())
+}
+
+fn close_setup(expect: &mut Expect) -> Result<(), SerialError> {
+    expect.step("close settings prompt");
+    expect.send_expect("0", "suc stp", PROMPT_TIMEOUT)?;
+    Ok(())
+}
+
+// Leaves a submenu whose changes only apply after a restart
+fn save_and_restart(expect: &mut Expect, step: &str) -> Result<(), SerialError> {
+    expect.step(step);
+    expect.send_expect("0", YES_NO, PROMPT_TIMEOUT)?;
+    expect.send_expect("y", READY, BOOT_TIMEOUT)?;
+    Ok(())
+}
+
+fn apply_wifi(expect: &mut Expect, settings: &Settings) -> Result<(), SerialError> {
+    open_setup(expect)?;
+    expect.step("open wifi settings");
+    let screen = expect.send_expect("1", WIFI, PROMPT_TIMEOUT)?;
+
+    if !screen.contains("CLIENT-MODE") {
+        expect.step("switch wifi to client mode");
+        expect.send_expect("1", "Client-Mode]: ", PROMPT_TIMEOUT)?;
+        expect.send_expect("2", WIFI, PROMPT_TIMEOUT)?;
+        expect.progress("disabled AP-Mode");
+    }
+
+    expect.step("set SSID");
+    expect.send_expect("2", "SSID: ", PROMPT_TIMEOUT)?;
+    expect.send_expect(&settings.ssid, WIFI, PROMPT_TIMEOUT)?;
+    expect.progress("set SSID");
+
+    expect.step("set password");
+    expect.send_expect("3", "Password: ", PROMPT_TIMEOUT)?;
+    expect.send_expect(&settings.password, WIFI, PROMPT_TIMEOUT)?;
+    expect.progress("set password");
+
+    save_and_restart(expect, "save wifi settings")
+}
+
+fn apply_led_num(expect: &mut Expect, settings: &Settings) -> Result<(), SerialError> {
+    open_setup(expect)?;
+    expect.step("open webserver settings");
+    let screen = expect.send_expect("2", WEB_SERVER, PROMPT_TIMEOUT)?;
+
+    // the number of ftPixels can only be changed while the web UI is on
+    if !screen.contains("ftPixels") {
+        expect.progress("web UI is off, led num left unchanged");
+        expect.step("close webserver settings");
+        expect.send_expect("0", MAIN, PROMPT_TIMEOUT)?;
+        return close_setup(expect);
+    }
+
+    expect.step("set led num");
+    expect.send_expect("2", "[2..18]: ", PROMPT_TIMEOUT)?;
+    expect.send_expect(&settings.rgb_led_num.to_string(), WEB_SERVER, PROMPT_TIMEOUT)?;
+    expect.progress("set led num");
+
+    save_and_restart(expect, "save webserver settings")
+}
+
+pub fn parse_swarm_name(screen: &str) -> Option<String> {
+    let rest = screen.split("connected to swarm \"").nth(1)?;
+    rest.split('"').next().map(|x| x.to_string())
+}
+
+fn apply_swarm(expect: &mut Expect, settings: &Settings) -> Result<(), SerialError> {
+    expect.step("open swarm settings");
+    let screen = expect.send_expect("3", SWARM, PROMPT_TIMEOUT)?;
+
+    if parse_swarm_name(&screen).as_deref() == Some(settings.swarm_name.as_str()) {
+        if parse_swarm_pin(&screen).as_deref() != Some(settings.swarm_pin.as_str()) {
+            expect.step("close swarm settings");
+            expect.send_expect("0", MAIN, PROMPT_TIMEOUT)?;
+            close_setup(expect)?;
+            return Err(rejected("join swarm", PIN_ONLY.to_string()));
+        }
+        expect.progress("already in swarm");
+    } else {
+        let (step, item) = if settings.create_swarm {
+            ("create swarm", "2")
+        } else {
+            ("join swarm", "3")
+        };
+
+        expect.step(step);
+        expect.send_expect(item, "[minimum 5 chars]: ", PROMPT_TIMEOUT)?;
+        expect.send_expect(&settings.swarm_name, "[1..9999]: ", PROMPT_TIMEOUT)?;
+        expect.send_expect(&settings.swarm_pin, "(Y/N) ?", PROMPT_TIMEOUT)?;
+        expect.send("y")?;
+
+        let (result, _) = expect.expect_any(&["sucessfully.", "ERROR: swarm"], JOIN_TIMEOUT)?;
+        if result == 1 {
+            return Err(rejected(step, format!("swarm \"{}\" not found", settings.swarm_name)));
+        }
+        expect.expect(SWARM, PROMPT_TIMEOUT)?;
+
+        expect.progress(if settings.create_swarm { "created swarm" } else { "joined swarm" });
+    }
+
+    expect.step("close swarm settings");
+    expect.send_expect("0", MAIN, PROMPT_TIMEOUT)?;
+    Ok(())
+}
+
+fn apply_aliases(expect: &mut Expect, settings: &Settings, names: &[String]) -> Result<(), SerialError> {
+    expect.step("open alias settings");
+    let screen = expect.send_expect("4", ALIAS, PROMPT_TIMEOUT)?;
+    let table = parse_alias_table(&screen);
+
+    for (name, alias) in alias_targets(settings) {
+        if !names.contains(&name) {
+            continue;
+        }
+        let step = format!("set alias {}", name);
+        expect.step(&step);
+
+        let Some(entry) = table.iter().find(|x| x.name == name) else {
+            if alias.is_empty() {
+                continue;
+            }
+            return Err(rejected(&step, format!("the board has no port {}", name)));
+        };
+
+        expect.send_expect(&entry.item.to_string(), "please enter new alias: ", PROMPT_TIMEOUT)?;
+        expect.send_expect(&alias, ALIAS, PROMPT_TIMEOUT)?;
+        expect.progress(&step);
+        expect.advance();
+    }
+
+    expect.step("save aliases");
+    expect.send("0")?;
+    if expect.expect_any(&[YES_NO, MAIN], PROMPT_TIMEOUT)?.0 == 0 {
+        expect.send_expect("y", MAIN, PROMPT_TIMEOUT)?;
+    }
+    Ok(())
+}
+
+// One field of the post-apply report. `actual` is None for fields the menus
+// don't show.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub field: String,
+    pub expected: String,
+    pub actual: Option<String>,
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        self.actual.as_ref().is_none_or(|actual| *actual == self.expected)
+    }
+}
+
+// Compares every field of `target` with what the board reports
+pub fn verify(current: &BoardConfig, target: &Settings) -> Vec<Check> {
+    let check = |field: &str, expected: String, actual: Option<String>| Check { field: field.to_string(), expected, actual };
+    let board = &current.settings;
+    let swarm_type = |x: Type| format!("{:?}", x);
+    // the report is shown and printed, the password never is
+    let password = if target.password.is_empty() { "" } else { "******" };
+
+    let mut checks = vec![
+        check("ssid", target.ssid.clone(), Some(board.ssid.clone())),
+        check("password", password.to_string(), None),
+        check("rgb led num", target.rgb_led_num.to_string(), Some(board.rgb_led_num.to_string())),
+        check("create swarm", target.create_swarm.to_string(), None),
+        check("swarm name", target.swarm_name.clone(), Some(board.swarm_name.clone())),
+        check("swarm pin", target.swarm_pin.clone(), Some(board.swarm_pin.clone())),
+        check("swarm type", swarm_type(target.swarm_type), Some(swarm_type(board.swarm_type))),
+    ];
+
+    // alias_targets() starts with the hostname
+    let aliases = alias_targets(board);
+    for (name, expected) in alias_targets(target) {
+        let actual = aliases.iter().find(|x| x.0 == name).map(|x| x.1.clone());
+        // a port the board doesn't have only passes without an alias
+        let actual = actual.or_else(|| (!expected.is_empty()).then(|| "(no such port)".to_string()));
+        checks.push(check(&name, expected, Some(actual.unwrap_or_default())));
+    }
+
+    checks
+}
+
+// Writes `settings` through the setup menus. Unless `full` is set only the
+// menus whose values differ from what the board reports are visited,
+// otherwise the board is reset and everything is rewritten. Returns the
+// board's configuration checked against `settings`.
+pub fn apply(expect: &mut Expect, settings: &Settings, full: bool) -> Result<Vec<Check>, SerialError> {
+    validate(settings)?;
+
+    let (diff, current) = if full {
+        let diff = Diff::all(settings);
+        expect.plan(1 + diff.steps() + READ_STEPS);
+        expect.progress("resetting");
+        reset(expect)?;
+        expect.progress("reset successful");
+        expect.advance();
+        (diff, None)
+    } else {
+        expect.plan(2 * READ_STEPS);
+        expect.progress("reading current configuration");
+        let current = read_config(expect, settings)?;
+        let diff = diff(&current, settings);
+        if !current.web_ui && current.settings.rgb_led_num != settings.rgb_led_num {
+            expect.progress("web UI is off, led num left unchanged");
+        }
+        // nothing is written, the check afterwards would fail anyway
+        if current.settings.swarm_name == settings.swarm_name && current.settings.swarm_pin != settings.swarm_pin {
+            return Err(rejected("compare settings", PIN_ONLY.to_string()));
+        }
+        expect.plan(READ_STEPS + diff.steps() + READ_STEPS);
+        (diff, Some(current))
+    };
+
+    if let Some(current) = &current {
+        if diff.is_empty() {
+            expect.progress("board is up to date");
+            return Ok(verify(current, settings));
+        }
+    }
+    expect.progress(&format!("changing {}", diff.describe()));
+
+    if diff.wifi {
+        apply_wifi(expect, settings)?;
+        expect.advance();
+    }
+    if diff.led_num {
+        apply_led_num(expect, settings)?;
+        expect.advance();
+    }
+
+    if diff.swarm || !diff.aliases.is_empty() {
+        open_setup(expect)?;
+        if diff.swarm {
+            apply_swarm(expect, settings)?;
+            expect.advance();
+        }
+        if !diff.aliases.is_empty() {
+            apply_aliases(expect, settings, &diff.aliases)?;
+        }
+        close_setup(expect)?;
+    }
+
+    expect.progress("verifying");
+    let current = read_config(expect, settings)?;
+    Ok(verify(&current, settings))
+}
+
+// Brings a board that was left somewhere in the setup menus back to the
+// sketch. Prompts are left with escape, save prompts are answered with no,
+// and the board is reset afterwards so edits that were never saved don't
+// linger in memory.
+pub fn recover(expect: &mut Expect) -> Result<(), SerialError> {
+    let failed_step = expect.current_step().to_string();
+    expect.step("back to main prompt");
+    let mut discarded = false;
+
+    for _ in 0..12 {
+        expect.discard(Duration::from_millis(300))?;
+        let tail = expect.tail();
+
+        if tail.ends_with(YES_NO) || tail.ends_with("(Y/N) ?") {
+            expect.send("n")?;
+            discarded = true;
+        } else if tail.ends_with("[1..9999]: ") {
+            // the swarm pin prompt insists on a pin, the confirmation
+            // following it is answered with no
+            expect.send("1")?;
+        } else if tail.ends_with(MAIN) {
+            expect.send("0")?;
+        } else if tail.ends_with('>') || tail.ends_with(": ") || tail.ends_with("]:") {
+            // escape takes a submenu's default, which is exit
+            expect.send_raw(b"\x1b")?;
+            discarded = true;
+        } else {
+            // no prompt, the sketch is running
+            expect.progress(&format!("board is back at the sketch prompt after \"{}\"", failed_step));
+            if discarded {
+                expect.progress("resetting to drop unsaved changes");
+                reset(expect)?;
+            }
+            return Ok(());
+        }
+    }
+
+    Err(rejected("back to main prompt", "the board keeps prompting".to_string()))
+}
+
+// Value printed after `label` on a menu line, e.g. "(2) SSID:     abab"
+fn parse_field(screen: &str, label: &str) -> Option<String> {
+    screen
+        .lines()
+        .find_map(|line| line.find(label).map(|at| line[at + label.len()..].trim().to_string()))
+}
+
+pub fn parse_swarm_pin(screen: &str) -> Option<String> {
+    parse_field(screen, "Swarm PIN is").map(|x| x.trim_end_matches('.').to_string())
+}
+
+// "(2) Show 3 ftPixels in UI", only shown while the web UI is on
+pub fn parse_led_num(screen: &str) -> Option<u8> {
+    parse_field(screen, "Show")?.split_whitespace().next()?.parse().ok()
+}
+
+// What the setup menus show of a board's configuration
+pub struct BoardConfig {
+    pub settings: Settings,
+    pub client_mode: bool,
+    // the LED count can only be changed while the web UI is on
+    pub web_ui: bool,
+}
+
+// Walks the setup menus and reads the current configuration. The menus never
+// show the wifi password or whether the swarm was created or joined, those
+// are kept from `base`.
+pub fn read(expect: &mut Expect, base: &Settings) -> Result<Settings, SerialError> {
+    expect.plan(READ_STEPS);
+    read_config(expect, base).map(|config| config.settings)
+}
+
+// Units of work of read_config(), one per menu
+const READ_STEPS: usize = 4;
+
+fn read_config(expect: &mut Expect, base: &Settings) -> Result<BoardConfig, SerialError> {
+    let mut settings = base.clone();
+
+    expect.step("wait for sketch");
+    expect.discard(Duration::from_millis(200))?;
+    open_setup(expect)?;
+
+    expect.step("read wifi settings");
+    let screen = expect.send_expect("1", WIFI, PROMPT_TIMEOUT)?;
+    if !screen.contains("(1) wifi:") {
+        return Err(unexpected("read wifi settings", "no wifi mode in the wifi menu"));
+    }
+    // only shown while wifi is on
+    if let Some(ssid) = parse_field(&screen, "SSID:") {
+        settings.ssid = ssid;
+    }
+    let client_mode = screen.contains("CLIENT-MODE");
+    expect.send_expect("0", MAIN, PROMPT_TIMEOUT)?;
+    expect.advance();
+
+    expect.step("read webserver settings");
+    let screen = expect.send_expect("2", WEB_SERVER, PROMPT_TIMEOUT)?;
+    let led_num = parse_led_num(&screen);
+    expect.send_expect("0", MAIN, PROMPT_TIMEOUT)?;
+    expect.advance();
+
+    expect.step("read swarm settings");
+    let screen = expect.send_expect("3", SWARM, PROMPT_TIMEOUT)?;
+    match (parse_swarm_name(&screen), parse_swarm_pin(&screen)) {
+        (Some(name), Some(pin)) => {
+            settings.swarm_name = name;
+            settings.swarm_pin = pin;
+        }
+        _ => return Err(unexpected("read swarm settings", "no swarm name or pin in the swarm menu")),
+    }
+    // controllers with RS485 offer "(1) swarm communication"
+    settings.swarm_type = if screen.contains("(1) swarm communication") { Type::RS485 } else { Type::JST };
+    expect.send_expect("0", MAIN, PROMPT_TIMEOUT)?;
+    expect.advance();
+
+    expect.step("read aliases");
+    let screen = expect.send_expect("4", ALIAS, PROMPT_TIMEOUT)?;
+    let table = parse_alias_table(&screen);
+    if !table.iter().any(|x| x.name == "hostname") {
+        return Err(unexpected("read aliases", "no hostname in the alias table"));
+    }
+    expect.send_expect("0", MAIN, PROMPT_TIMEOUT)?;
+    close_setup(expect)?;
+    expect.advance();
+
+    let alias = |name: String| table.iter().find(|x| x.name == name).map(|x| x.alias.clone()).unwrap_or_default();
+    let leds = table.iter().filter(|x| x.name.starts_with("LED")).count() as u8;
+    let inputs = if settings.swarm_type == Type::RS485 { 6 } else { 4 };
+
+    settings.rgb_led_num = led_num.unwrap_or(leds);
+    settings.hostname = alias("hostname".to_string());
+    settings.input_ports = (1..=inputs).map(|i| alias(format!("A{}", i))).collect();
+    settings.output_ports = (1..=2).map(|i| alias(format!("M{}", i))).collect();
+    settings.led_ports = (1..=settings.rgb_led_num).map(|i| alias(format!("LED{}", i))).collect();
+    settings.servo_port = alias("SERVO".to_string());
+
+    Ok(BoardConfig { settings, client_mode, web_ui: led_num.is_some() })
+}
+
+// Menus an apply has to go through to get from `current` to `target`
+pub struct Diff {
+    pub wifi: bool,
+    pub led_num: bool,
+    pub swarm: bool,
+    // ports whose alias changes
+    pub aliases: Vec<String>,
+}
+
+impl Diff {
+    // Rewrites everything, as the password can't be compared
+    pub fn all(target: &Settings) -> Diff {
+        Diff {
+            wifi: true,
+            led_num: true,
+            swarm: true,
+            aliases: alias_targets(target).into_iter().map(|(name, _)| name).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.wifi && !self.led_num && !self.swarm && self.aliases.is_empty()
+    }
+
+    // Units of work to apply the changes, one per menu and alias
+    fn steps(&self) -> usize {
+        self.wifi as usize + self.led_num as usize + self.swarm as usize + self.aliases.len()
+    }
+
+    fn describe(&self) -> String {
+        let mut parts = vec![];
+        if self.wifi {
+            parts.push("wifi".to_string());
+        }
+        if self.led_num {
+            parts.push("led num".to_string());
+        }
+        if self.swarm {
+            parts.push("swarm".to_string());
+        }
+        if !self.aliases.is_empty() {
+            parts.push(format!("aliases {}", self.aliases.join(", ")));
+        }
+        parts.join(", ")
+    }
+}
+
+// The password is only written together with the SSID, a changed password
+// alone isn't noticed. Likewise the firmware only takes a new swarm pin
+// together with a new swarm name.
+pub fn diff(current: &BoardConfig, target: &Settings) -> Diff {
+    let aliases = alias_targets(&current.settings);
+    let alias = |name: &str| aliases.iter().find(|x| x.0 == name).map(|x| x.1.as_str()).unwrap_or("");
+
+    Diff {
+        wifi: !current.client_mode || current.settings.ssid != target.ssid,
+        led_num: current.web_ui && current.settings.rgb_led_num != target.rgb_led_num,
+        swarm: current.settings.swarm_name != target.swarm_name,
+        aliases: alias_targets(target)
+            .into_iter()
+            .filter(|(name, target)| alias(name) != target)
+            .map(|(name, _)| name)
+            .collect(),
+    }
+}
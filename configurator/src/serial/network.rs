@@ -0,0 +1,220 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+use super::profile::{Flow, ParityMode, Profile, Signal};
+use super::transport::{Transport, DEFAULT_TIMEOUT};
+
+// Ports of a serial bridge on the network, e.g. ser2net: the bytes as they
+// are, or telnet with the RFC 2217 com port option for the line parameters
+const RAW_PREFIX: &str = "tcp://";
+const RFC2217_PREFIX: &str = "rfc2217://";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+// telnet
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+const BINARY: u8 = 0;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+
+// RFC 2217 commands from the client
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+
+pub fn is_network(port: &str) -> bool {
+    port.starts_with(RAW_PREFIX) || port.starts_with(RFC2217_PREFIX)
+}
+
+// Where telnet commands in the received bytes are
+#[derive(Clone, Copy)]
+enum Telnet {
+    Data,
+    Iac,
+    // DO, DONT, WILL or WONT waiting for its option
+    Negotiation(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+pub struct NetworkTransport {
+    stream: TcpStream,
+    // telnet with RFC 2217, None for a raw bridge
+    telnet: Option<Telnet>,
+}
+
+impl NetworkTransport {
+    // The line parameters of the profile only reach the port through RFC
+    // 2217, a raw bridge uses whatever it was set up with
+    pub fn open(port: &str, profile: &Profile) -> io::Result<NetworkTransport> {
+        let (address, rfc2217) = match port.strip_prefix(RFC2217_PREFIX) {
+            Some(address) => (address, true),
+            None => (port.strip_prefix(RAW_PREFIX).unwrap_or(port), false),
+        };
+
+        let address = address.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no address for {}", address)))?;
+        let stream = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT)?;
+        // the sketch takes a pause as the end of a command, so nothing may
+        // be held back
+        stream.set_nodelay(true)?;
+        // reads wait as long as the caller asks, writes as long as it takes
+        // to connect
+        stream.set_write_timeout(Some(CONNECT_TIMEOUT))?;
+
+        let mut transport = NetworkTransport { stream, telnet: None };
+        transport.set_timeout(DEFAULT_TIMEOUT)?;
+        if rfc2217 {
+            transport.telnet = Some(Telnet::Data);
+            transport.configure(profile)?;
+        }
+        Ok(transport)
+    }
+
+    fn configure(&mut self, profile: &Profile) -> io::Result<()> {
+        let mut data = vec![
+            IAC, WILL, BINARY, IAC, DO, BINARY,
+            IAC, DO, SUPPRESS_GO_AHEAD,
+            IAC, WILL, COM_PORT_OPTION,
+        ];
+
+        let mut command = |code: u8, value: &[u8]| {
+            data.extend([IAC, SB, COM_PORT_OPTION, code]);
+            for &byte in value {
+                data.push(byte);
+                if byte == IAC {
+                    data.push(IAC);
+                }
+            }
+            data.extend([IAC, SE]);
+        };
+
+        command(SET_BAUDRATE, &profile.baud_rate.to_be_bytes());
+        command(SET_DATASIZE, &[profile.data_bits]);
+        command(SET_PARITY, &[match profile.parity {
+            ParityMode::None => 1,
+            ParityMode::Odd => 2,
+            ParityMode::Even => 3,
+        }]);
+        command(SET_STOPSIZE, &[if profile.stop_bits == 2 { 2 } else { 1 }]);
+        command(SET_CONTROL, &[match profile.flow_control {
+            Flow::None => 1,
+            Flow::XonXoff => 2,
+            Flow::RtsCts => 3,
+        }]);
+        match profile.dtr {
+            Signal::Unchanged => {}
+            Signal::Set => command(SET_CONTROL, &[8]),
+            Signal::Cleared => command(SET_CONTROL, &[9]),
+        }
+        match profile.rts {
+            Signal::Unchanged => {}
+            Signal::Set => command(SET_CONTROL, &[11]),
+            Signal::Cleared => command(SET_CONTROL, &[12]),
+        }
+
+        self.stream.write_all(&data)
+    }
+
+    // Drops telnet commands from received bytes, in place. Options the
+    // bridge asks for that weren't offered are refused.
+    fn strip_telnet(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(mut state) = self.telnet else { return Ok(buf.len()) };
+        let mut reply = vec![];
+        let mut n = 0;
+
+        for i in 0..buf.len() {
+            let byte = buf[i];
+            state = match (state, byte) {
+                (Telnet::Data, IAC) => Telnet::Iac,
+                (Telnet::Data, _) => {
+                    buf[n] = byte;
+                    n += 1;
+                    Telnet::Data
+                }
+                (Telnet::Iac, IAC) => {
+                    buf[n] = IAC;
+                    n += 1;
+                    Telnet::Data
+                }
+                (Telnet::Iac, DO | DONT | WILL | WONT) => Telnet::Negotiation(byte),
+                (Telnet::Iac, SB) => Telnet::Subnegotiation,
+                (Telnet::Iac, _) => Telnet::Data,
+                (Telnet::Negotiation(command), option) => {
+                    match command {
+                        DO if option != BINARY && option != COM_PORT_OPTION => reply.extend([IAC, WONT, option]),
+                        WILL if option != BINARY && option != SUPPRESS_GO_AHEAD => reply.extend([IAC, DONT, option]),
+                        _ => {}
+                    }
+                    Telnet::Data
+                }
+                // answers to the com port commands aren't needed
+                (Telnet::Subnegotiation, IAC) => Telnet::SubnegotiationIac,
+                (Telnet::Subnegotiation, _) => Telnet::Subnegotiation,
+                (Telnet::SubnegotiationIac, SE) => Telnet::Data,
+                (Telnet::SubnegotiationIac, _) => Telnet::Subnegotiation,
+            };
+        }
+
+        self.telnet = Some(state);
+        if !reply.is_empty() {
+            self.stream.write_all(&reply)?;
+        }
+        Ok(n)
+    }
+}
+
+impl Transport for NetworkTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // telnet commands alone don't count as received data
+        loop {
+            let n = match self.stream.read(buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Err(io::ErrorKind::TimedOut.into()),
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                return Ok(0);
+            }
+
+            let n = self.strip_telnet(&mut buf[..n])?;
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.telnet.is_none() {
+            return self.stream.write(buf);
+        }
+
+        let mut data = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            data.push(byte);
+            if byte == IAC {
+                data.push(IAC);
+            }
+        }
+        self.stream.write_all(&data)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        // a zero timeout is refused by sockets
+        self.stream.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use super::profile::Profile;
+use super::protocol::SketchEvent;
+use super::transport::{self, Claim, Transport};
+
+// Silence after which the board is considered idle
+const QUIET: Duration = Duration::from_millis(500);
+// A board reset by opening the port gets this long to reach the sketch
+const BOOT: Duration = Duration::from_secs(5);
+// Wait for the answer to "nod"
+const REPLY: Duration = Duration::from_secs(1);
+// Received text kept for the analysis
+const MAX_TEXT: usize = 4096;
+
+// What was found on a serial port
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Detected {
+    // the board sketch is running and takes commands
+    Sketch { firmware: Option<String>, host: Option<String> },
+    // ftSwarmOS without the sketch prompt, e.g. sitting in the setup menu
+    Firmware { firmware: Option<String>, host: Option<String> },
+    // something sent data, but it doesn't look like an ftSwarm
+    Unknown,
+    // nothing answered
+    Silent,
+    // opened by a session of this configurator
+    InUse,
+    Unavailable(String),
+}
+
+impl fmt::Display for Detected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let board = |f: &mut fmt::Formatter<'_>, firmware: &Option<String>, host: &Option<String>, state: &str| {
+            write!(f, "ftSwarm")?;
+            if let Some(host) = host {
+                write!(f, " {}", host)?;
+            }
+            write!(f, " ({}", state)?;
+            if let Some(firmware) = firmware {
+                write!(f, ", ftSwarmOS {}", firmware)?;
+            }
+            write!(f, ")")
+        };
+
+        match self {
+            Detected::Sketch { firmware, host } => board(f, firmware, host, "sketch ready"),
+            Detected::Firmware { firmware, host } => board(f, firmware, host, "no sketch prompt"),
+            Detected::Unknown => write!(f, "unknown device"),
+            Detected::Silent => write!(f, "no answer"),
+            Detected::InUse => write!(f, "in use"),
+            Detected::Unavailable(reason) => write!(f, "unavailable: {}", reason),
+        }
+    }
+}
+
+// A port worth probing, USB adapters and bridges, and what tells the
+// device behind it apart: the USB identity, or the port for a bridge
+pub type Candidate = (String, String);
+
+// What the prober found and what it still has to do, shared by all sessions
+// so each port is probed once
+struct Probes {
+    // port -> candidate key and result
+    found: BTreeMap<String, (String, Detected)>,
+    queue: VecDeque<Candidate>,
+    // the port being probed now
+    current: Option<String>,
+}
+
+static PROBES: Mutex<Probes> = Mutex::new(Probes { found: BTreeMap::new(), queue: VecDeque::new(), current: None });
+
+// Queues the candidates that weren't probed yet, have another device now or
+// were in use. They are probed one after the other on a thread of its own,
+// so connecting doesn't wait for them.
+pub fn request(candidates: &[Candidate]) {
+    let mut probes = PROBES.lock().unwrap();
+    for (port, key) in candidates {
+        let known = probes.found.get(port).is_some_and(|(found, detected)| found == key && *detected != Detected::InUse);
+        let queued = probes.queue.iter().any(|x| x.0 == *port) || probes.current.as_ref() == Some(port);
+        if !known && !queued {
+            probes.queue.push_back((port.clone(), key.clone()));
+        }
+    }
+
+    if probes.current.is_none() && !probes.queue.is_empty() {
+        probes.current = probes.queue.front().map(|x| x.0.clone());
+        thread::spawn(run);
+    }
+}
+
+fn run() {
+    loop {
+        let Some((port, key)) = PROBES.lock().unwrap().queue.pop_front() else { break };
+        let detected = probe_port(&port);
+
+        let mut probes = PROBES.lock().unwrap();
+        probes.found.insert(port, (key, detected));
+        match probes.queue.front() {
+            Some((next, _)) => probes.current = Some(next.clone()),
+            None => {
+                probes.current = None;
+                break;
+            }
+        }
+    }
+}
+
+fn probe_port(port: &str) -> Detected {
+    let Some(_claim) = Claim::take(port) else {
+        return Detected::InUse;
+    };
+
+    // boards are looked for with the sketch's line parameters
+    match transport::open(port, &Profile::default()) {
+        Ok(mut serial) => probe(&mut *serial),
+        Err(error) => Detected::Unavailable(error.to_string()),
+    }
+}
+
+// The port is probed again with the next request, e.g. after a session
+// had it open and may have changed the board
+pub fn forget(port: &str) {
+    PROBES.lock().unwrap().found.remove(port);
+}
+
+// Waits until the prober let go of `port`, so connecting to it doesn't fail
+// as in use
+pub fn wait(port: &str) {
+    let deadline = Instant::now() + BOOT + REPLY + QUIET;
+    while PROBES.lock().unwrap().current.as_deref() == Some(port) && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// What was found on the candidates so far
+pub fn results(candidates: &[Candidate]) -> BTreeMap<String, Detected> {
+    let probes = PROBES.lock().unwrap();
+    candidates.iter()
+        .filter_map(|(port, key)| match probes.found.get(port) {
+            Some((found, detected)) if found == key => Some((port.clone(), detected.clone())),
+            _ => None,
+        })
+        .collect()
+}
+
+// Some of the candidates are still waiting or being probed
+pub fn pending(candidates: &[Candidate]) -> bool {
+    let probes = PROBES.lock().unwrap();
+    candidates.iter().any(|(port, _)| probes.current.as_ref() == Some(port) || probes.queue.iter().any(|x| x.0 == *port))
+}
+
+// Finds out what is connected to a freshly opened port without changing
+// its state. Boot output is waited for, since a key press during the boot
+// enters the bios settings. Only a port that stays silent is asked with
+// "nod", which the sketch answers with "suc nod".
+pub fn probe(transport: &mut dyn Transport) -> Detected {
+    let mut text = String::new();
+
+    let result = listen(transport, &mut text, BOOT, |text, quiet| {
+        text.contains(">>>") || text.contains("main>") || (quiet && !text.contains("ftSwarmOS"))
+    });
+    if let Err(error) = result {
+        return Detected::Unavailable(error.to_string());
+    }
+
+    if text.is_empty() {
+        let result = transport.write_all(b"nod\n").and_then(|_| transport.flush()).and_then(|_| {
+            listen(transport, &mut text, REPLY, |text, quiet| text.contains("suc nod") || quiet)
+        });
+        if let Err(error) = result {
+            return Detected::Unavailable(error.to_string());
+        }
+    }
+
+    classify(&text)
+}
+
+// Reads until `done(text, quiet)` holds or `limit` is up. `quiet` is set
+// once nothing arrived for a while.
+fn listen(
+    transport: &mut dyn Transport,
+    text: &mut String,
+    limit: Duration,
+    done: impl Fn(&str, bool) -> bool,
+) -> io::Result<()> {
+    let start = Instant::now();
+    let mut last_data = start;
+    let mut buffer = [0; 256];
+    transport.set_timeout(Duration::from_millis(100))?;
+
+    while start.elapsed() < limit {
+        match transport.read(&mut buffer) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                if text.len() < MAX_TEXT {
+                    text.push_str(&String::from_utf8_lossy(&buffer[..n]));
+                }
+                last_data = Instant::now();
+            }
+            Err(e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted) => {}
+            Err(e) => return Err(e),
+        }
+
+        if done(text, last_data.elapsed() >= QUIET) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn classify(text: &str) -> Detected {
+    let word_after = |marker: &str| {
+        text.find(marker)
+            .and_then(|i| text[i + marker.len()..].split_whitespace().next())
+            .map(|x| x.to_string())
+    };
+    // "ftSwarmOS 0.5.0" banner and "Boot ftSwarm101 (SN:101)."
+    let firmware = word_after("ftSwarmOS ");
+    let host = word_after("Boot ");
+
+    // the prompt or any reply of the sketch, "nod" may also have ended a
+    // forgotten setup menu which the sketch confirms with "suc stp"
+    let sketch = text.lines().map(SketchEvent::parse).any(|x| {
+        matches!(x, SketchEvent::Ready | SketchEvent::Success { .. } | SketchEvent::Error { .. })
+    });
+
+    if sketch {
+        Detected::Sketch { firmware, host }
+    } else if firmware.is_some() || text.contains("main>") {
+        Detected::Firmware { firmware, host }
+    } else if text.trim().is_empty() {
+        Detected::Silent
+    } else {
+        Detected::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::simulator::Simulator;
+    use crate::serial::transport::MemoryTransport;
+
+    fn some(text: &str) -> Option<String> {
+        Some(text.to_string())
+    }
+
+    #[test]
+    fn classify_boot_output_and_replies() {
+        let boot = "\n\nftSwarmOS 0.5.0\n\nPress any key to enter bios settings.\nBoot ftSwarm101 (SN:101).\n";
+        assert_eq!(classify(&format!("{}>>>\r\n", boot)), Detected::Sketch { firmware: some("0.5.0"), host: some("ftSwarm101") });
+        assert_eq!(classify(&format!("{}main> ", boot)), Detected::Firmware { firmware: some("0.5.0"), host: some("ftSwarm101") });
+        assert_eq!(classify(boot), Detected::Firmware { firmware: some("0.5.0"), host: some("ftSwarm101") });
+
+        // a board that was running already only answers "nod"
+        assert_eq!(classify("suc nod 1 nodes\r\n"), Detected::Sketch { firmware: None, host: None });
+        assert_eq!(classify("suc stp\r\n"), Detected::Sketch { firmware: None, host: None });
+        assert_eq!(classify("err nod\r\n"), Detected::Sketch { firmware: None, host: None });
+        assert_eq!(classify("(0) exit\nmain> "), Detected::Firmware { firmware: None, host: None });
+
+        assert_eq!(classify("$GPGGA,123519,4807.038,N\r\n"), Detected::Unknown);
+        assert_eq!(classify(""), Detected::Silent);
+        assert_eq!(classify("\r\n \n"), Detected::Silent);
+    }
+
+    #[test]
+    fn probe_a_booting_board() {
+        let board = Simulator::new(100);
+        let mut transport = board.connect();
+        assert_eq!(probe(&mut transport), Detected::Sketch { firmware: some("0.5.0"), host: some("ftSwarm100") });
+    }
+
+    #[test]
+    fn probe_a_silent_port() {
+        let (mut host, _board) = MemoryTransport::pair();
+        assert_eq!(probe(&mut host), Detected::Silent);
+    }
+}
@@ -0,0 +1,124 @@
+use serde::{Serialize, Deserialize};
+use serial2::{CharSize, FlowControl, Parity, SerialPort, StopBits};
+
+// Line parameters of a port and how the configurator talks on it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    pub baud_rate: u32,
+    // 5 to 8
+    pub data_bits: u8,
+    pub parity: ParityMode,
+    // 1 or 2
+    pub stop_bits: u8,
+    pub flow_control: Flow,
+    // modem lines after opening, ESP32 boards reset on some combinations
+    pub dtr: Signal,
+    pub rts: Signal,
+    // ending of lines sent from the console, the setup menus always get
+    // a single "\n"
+    pub line_ending: LineEnding,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParityMode {
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Flow {
+    None,
+    XonXoff,
+    RtsCts,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Signal {
+    // whatever the driver does when opening
+    Unchanged,
+    Set,
+    Cleared,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+// The board sketch: 115200 8N1
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            name: "default".to_string(),
+            baud_rate: 115200,
+            data_bits: 8,
+            parity: ParityMode::None,
+            stop_bits: 1,
+            flow_control: Flow::None,
+            dtr: Signal::Unchanged,
+            rts: Signal::Unchanged,
+            line_ending: LineEnding::Lf,
+        }
+    }
+}
+
+impl Profile {
+    // Opens a port with these parameters
+    pub fn open(&self, path: &str) -> std::io::Result<SerialPort> {
+        let port = SerialPort::open(path, |mut settings: serial2::Settings| {
+            settings.set_baud_rate(self.baud_rate)?;
+            settings.set_char_size(match self.data_bits {
+                5 => CharSize::Bits5,
+                6 => CharSize::Bits6,
+                7 => CharSize::Bits7,
+                _ => CharSize::Bits8,
+            });
+            settings.set_parity(match self.parity {
+                ParityMode::None => Parity::None,
+                ParityMode::Odd => Parity::Odd,
+                ParityMode::Even => Parity::Even,
+            });
+            settings.set_stop_bits(if self.stop_bits == 2 { StopBits::Two } else { StopBits::One });
+            settings.set_flow_control(match self.flow_control {
+                Flow::None => FlowControl::None,
+                Flow::XonXoff => FlowControl::XonXoff,
+                Flow::RtsCts => FlowControl::RtsCts,
+            });
+            Ok(settings)
+        })?;
+
+        match self.dtr {
+            Signal::Unchanged => {}
+            Signal::Set => port.set_dtr(true)?,
+            Signal::Cleared => port.set_dtr(false)?,
+        }
+        match self.rts {
+            Signal::Unchanged => {}
+            Signal::Set => port.set_rts(true)?,
+            Signal::Cleared => port.set_rts(false)?,
+        }
+        Ok(port)
+    }
+
+    // "115200 8N1"
+    pub fn describe(&self) -> String {
+        let parity = match self.parity {
+            ParityMode::None => 'N',
+            ParityMode::Odd => 'O',
+            ParityMode::Even => 'E',
+        };
+        format!("{} {}{}{}", self.baud_rate, self.data_bits, parity, self.stop_bits)
+    }
+}
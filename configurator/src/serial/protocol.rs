@@ -0,0 +1,228 @@
+use std::fmt;
+use std::str::FromStr;
+
+// Commands understood by the board sketch (src/main.cpp)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SketchCommand {
+    SubscribeDigital { name: String },
+    SubscribeAnalog { threshold: u16, name: String },
+    Motor { name: String, speed: i16 },
+    Led(bool),
+    OnTrigger { input: String, trigger: Trigger, output: String, value: i32 },
+    Nodes,
+    Setup,
+    Reset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Down,
+    Up,
+}
+
+impl SketchCommand {
+    // Encoded command without line ending
+    pub fn encode(&self) -> String {
+        match self {
+            SketchCommand::SubscribeDigital { name } => format!("sub digital {}", name),
+            SketchCommand::SubscribeAnalog { threshold, name } => format!("sub analog {} {}", threshold, name),
+            SketchCommand::Motor { name, speed } => format!("mot {} {}", name, speed),
+            SketchCommand::Led(true) => "led on".to_string(),
+            SketchCommand::Led(false) => "led off".to_string(),
+            SketchCommand::OnTrigger { input, trigger, output, value } => {
+                let action = match trigger {
+                    Trigger::Down => 0,
+                    Trigger::Up => 1,
+                };
+                format!("otr {} {} {} {}", input, action, output, value)
+            }
+            SketchCommand::Nodes => "nod".to_string(),
+            SketchCommand::Setup => "stp".to_string(),
+            SketchCommand::Reset => "res".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SketchCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.encode())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCommandError(pub String);
+
+impl fmt::Display for ParseCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid sketch command: {}", self.0)
+    }
+}
+
+impl FromStr for SketchCommand {
+    type Err = ParseCommandError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let line = s.trim();
+        let err = || ParseCommandError(line.to_string());
+        let mut parts = line.split_whitespace();
+
+        let command = match parts.next().ok_or_else(err)? {
+            "sub" => match parts.next().ok_or_else(err)? {
+                "digital" => SketchCommand::SubscribeDigital {
+                    name: parts.next().ok_or_else(err)?.to_string(),
+                },
+                "analog" => SketchCommand::SubscribeAnalog {
+                    threshold: parts.next().and_then(|x| x.parse().ok()).ok_or_else(err)?,
+                    name: parts.next().ok_or_else(err)?.to_string(),
+                },
+                _ => return Err(err()),
+            },
+            "mot" => SketchCommand::Motor {
+                name: parts.next().ok_or_else(err)?.to_string(),
+                speed: parts.next().and_then(|x| x.parse().ok()).ok_or_else(err)?,
+            },
+            "led" => match parts.next() {
+                Some("on") => SketchCommand::Led(true),
+                Some("off") => SketchCommand::Led(false),
+                _ => return Err(err()),
+            },
+            "otr" => SketchCommand::OnTrigger {
+                input: parts.next().ok_or_else(err)?.to_string(),
+                trigger: match parts.next() {
+                    Some("0") => Trigger::Down,
+                    Some("1") => Trigger::Up,
+                    _ => return Err(err()),
+                },
+                output: parts.next().ok_or_else(err)?.to_string(),
+                value: parts.next().and_then(|x| x.parse().ok()).ok_or_else(err)?,
+            },
+            "nod" => SketchCommand::Nodes,
+            "stp" => SketchCommand::Setup,
+            "res" => SketchCommand::Reset,
+            _ => return Err(err()),
+        };
+
+        if parts.next().is_some() {
+            return Err(err());
+        }
+
+        Ok(command)
+    }
+}
+
+// Lines printed by the board sketch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SketchEvent {
+    // ">>>" banner, printed once the sketch is ready for commands
+    Ready,
+    // "suc <cmd> [detail]"
+    Success { command: String, detail: Option<String> },
+    // "err <cmd>"
+    Error { command: String },
+    // "#debug <text>"
+    Debug(String),
+    // "!<name> <value>", sent for subscribed inputs
+    Value { name: String, value: i32 },
+    // anything else, e.g. ftSwarmOS boot output
+    Other(String),
+}
+
+impl SketchEvent {
+    pub fn parse(line: &str) -> SketchEvent {
+        let line = line.trim_end_matches(['\r', '\n']);
+
+        if line.trim() == ">>>" {
+            return SketchEvent::Ready;
+        }
+
+        if let Some(debug) = line.strip_prefix("#debug") {
+            return SketchEvent::Debug(debug.trim_start().to_string());
+        }
+
+        if let Some(value) = line.strip_prefix('!') {
+            if let Some((name, value)) = value.rsplit_once(' ') {
+                if let Ok(value) = value.trim().parse() {
+                    if !name.is_empty() {
+                        return SketchEvent::Value { name: name.to_string(), value };
+                    }
+                }
+            }
+        }
+
+        if let Some(rest) = line.strip_prefix("suc ") {
+            let mut parts = rest.trim().splitn(2, ' ');
+            if let Some(command) = parts.next().filter(|x| !x.is_empty()) {
+                return SketchEvent::Success {
+                    command: command.to_string(),
+                    detail: parts.next().map(|x| x.trim().to_string()),
+                };
+            }
+        }
+
+        if let Some(rest) = line.strip_prefix("err ") {
+            if !rest.trim().is_empty() {
+                return SketchEvent::Error { command: rest.trim().to_string() };
+            }
+        }
+
+        SketchEvent::Other(line.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_round_trip() {
+        let commands = [
+            SketchCommand::SubscribeDigital { name: "start".to_string() },
+            SketchCommand::SubscribeAnalog { threshold: 50, name: "light".to_string() },
+            SketchCommand::Motor { name: "belt".to_string(), speed: -128 },
+            SketchCommand::Led(true),
+            SketchCommand::Led(false),
+            SketchCommand::OnTrigger { input: "start".to_string(), trigger: Trigger::Down, output: "belt".to_string(), value: 255 },
+            SketchCommand::OnTrigger { input: "stop".to_string(), trigger: Trigger::Up, output: "belt".to_string(), value: 0 },
+            SketchCommand::Nodes,
+            SketchCommand::Setup,
+            SketchCommand::Reset,
+        ];
+        for command in commands {
+            assert_eq!(command.encode().parse::<SketchCommand>(), Ok(command.clone()), "{}", command);
+        }
+    }
+
+    #[test]
+    fn commands_tolerate_surrounding_space() {
+        assert_eq!("  mot belt 10\r\n".parse(), Ok(SketchCommand::Motor { name: "belt".to_string(), speed: 10 }));
+    }
+
+    #[test]
+    fn bad_commands_are_rejected() {
+        for line in ["", "foo", "sub", "sub both x", "sub analog x light", "mot belt", "mot belt fast", "led", "led dim", "otr a 2 b 1", "nod now", "res 1"] {
+            assert_eq!(line.parse::<SketchCommand>(), Err(ParseCommandError(line.to_string())), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn events() {
+        assert_eq!(SketchEvent::parse(">>>\r\n"), SketchEvent::Ready);
+        assert_eq!(SketchEvent::parse("#debug  motor on"), SketchEvent::Debug("motor on".to_string()));
+        assert_eq!(SketchEvent::parse("!light 512"), SketchEvent::Value { name: "light".to_string(), value: 512 });
+        assert_eq!(SketchEvent::parse("!left button -1"), SketchEvent::Value { name: "left button".to_string(), value: -1 });
+        assert_eq!(SketchEvent::parse("suc mot"), SketchEvent::Success { command: "mot".to_string(), detail: None });
+        assert_eq!(
+            SketchEvent::parse("suc nod 2 nodes"),
+            SketchEvent::Success { command: "nod".to_string(), detail: Some("2 nodes".to_string()) }
+        );
+        assert_eq!(SketchEvent::parse("err otr"), SketchEvent::Error { command: "otr".to_string() });
+        assert_eq!(SketchEvent::parse("ftSwarmOS 0.5.0"), SketchEvent::Other("ftSwarmOS 0.5.0".to_string()));
+    }
+
+    #[test]
+    fn malformed_events_are_other() {
+        for line in ["!light", "!light high", "! 12", "!", "suc", "suc ", "err", "err  ", "success mot", "error"] {
+            assert_eq!(SketchEvent::parse(line), SketchEvent::Other(line.to_string()), "{:?}", line);
+        }
+    }
+}
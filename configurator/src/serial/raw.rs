@@ -0,0 +1,154 @@
+use std::io;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use super::lines::{Direction, Line};
+use super::recording::Recorder;
+use super::transport::Transport;
+use super::worker::Event;
+
+// Bytes as they went over the line in one read or write
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub time: SystemTime,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+// Reports everything read from and written to a transport, and records it
+// while a recording is running
+pub struct Tap {
+    inner: Box<dyn Transport>,
+    events: Sender<Event>,
+    recorder: Arc<Mutex<Option<Recorder>>>,
+}
+
+impl Tap {
+    pub fn new(inner: Box<dyn Transport>, events: Sender<Event>, recorder: Arc<Mutex<Option<Recorder>>>) -> Tap {
+        Tap { inner, events, recorder }
+    }
+
+    fn report(&self, direction: Direction, data: &[u8]) {
+        if !data.is_empty() {
+            let chunk = Chunk { time: SystemTime::now(), direction, data: data.to_vec() };
+            let mut recorder = self.recorder.lock().unwrap();
+            // a recording that can't be written is stopped
+            if recorder.as_mut().is_some_and(|x| x.write(&chunk).is_err()) {
+                *recorder = None;
+                let message = "recording stopped, the session file can't be written".to_string();
+                let _ = self.events.send(Event::Line(Line::new(Direction::Local, message)));
+            }
+            let _ = self.events.send(Event::Bytes(chunk));
+        }
+    }
+}
+
+impl Transport for Tap {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.report(Direction::Rx, &buf[..n]);
+        Ok(n)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.report(Direction::Tx, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.inner.set_timeout(timeout)
+    }
+}
+
+// Text with escapes to bytes: \xHH, \r, \n, \t, \e, \0 and \\
+pub fn parse_bytes(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+
+        match chars.next() {
+            Some('x') => {
+                // exactly two digits, from_str_radix() would also take a sign
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = Some(&hex)
+                    .filter(|x| x.len() == 2 && x.chars().all(|x| x.is_ascii_hexdigit()))
+                    .and_then(|x| u8::from_str_radix(x, 16).ok());
+                bytes.push(byte.ok_or_else(|| format!("invalid escape \\x{}", hex))?);
+            }
+            Some('r') => bytes.push(b'\r'),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('e') => bytes.push(0x1b),
+            Some('0') => bytes.push(0),
+            Some('\\') => bytes.push(b'\\'),
+            Some(other) => return Err(format!("unknown escape \\{}", other)),
+            None => return Err("escape at the end".to_string()),
+        }
+    }
+
+    Ok(bytes)
+}
+
+// Bytes for a text line, printable ASCII as it is and everything else as
+// an escape parse_bytes() understands
+pub fn escape(data: &[u8]) -> String {
+    let mut text = String::new();
+    for &byte in data {
+        match byte {
+            b'\r' => text.push_str("\\r"),
+            b'\n' => text.push_str("\\n"),
+            b'\t' => text.push_str("\\t"),
+            b'\\' => text.push_str("\\\\"),
+            0x20..=0x7e => text.push(byte as char),
+            _ => text.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_escapes() {
+        assert_eq!(parse_bytes("stp\\r\\n").unwrap(), b"stp\r\n");
+        assert_eq!(parse_bytes("\\x1b[A\\e\\0\\t\\\\").unwrap(), b"\x1b[A\x1b\0\t\\");
+        assert_eq!(parse_bytes("\\xFF\\x0a").unwrap(), [0xff, 0x0a]);
+        assert_eq!(parse_bytes("Größe").unwrap(), "Größe".as_bytes());
+
+        for (text, error) in [
+            ("\\x+f", "invalid escape \\x+f"),
+            ("\\x-1", "invalid escape \\x-1"),
+            ("\\xg0", "invalid escape \\xg0"),
+            ("\\x1", "invalid escape \\x1"),
+            ("\\x", "invalid escape \\x"),
+            ("\\q", "unknown escape \\q"),
+            ("stp\\", "escape at the end"),
+        ] {
+            assert_eq!(parse_bytes(text), Err(error.to_string()), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        let every_byte: Vec<u8> = (0..=255).collect();
+        for data in [&every_byte[..], b"", b"stp\r\n", b"\\x41", b"\\\\", "Größe".as_bytes()] {
+            let text = escape(data);
+            assert!(text.chars().all(|x| (' '..='~').contains(&x)), "{:?}", text);
+            assert_eq!(parse_bytes(&text).unwrap(), data, "{:?}", text);
+        }
+        assert_eq!(escape(b"\x1bstp\r\n\\"), "\\x1bstp\\r\\n\\\\");
+    }
+}
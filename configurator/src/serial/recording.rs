@@ -0,0 +1,293 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use super::lines::Direction;
+use super::raw::Chunk;
+use super::transport::Transport;
+
+const VERSION: u32 = 1;
+// LINKTYPE_USER0, the payload is the bytes of one read or write
+const PCAPNG_LINKTYPE: u16 = 147;
+
+// First line of a session file, one Record per line follows
+#[derive(Serialize, Deserialize)]
+struct Header {
+    version: u32,
+    // microseconds since the unix epoch
+    started_us: u64,
+    port: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    // microseconds since the recording started
+    us: u64,
+    // "rx" or "tx"
+    dir: String,
+    hex: String,
+}
+
+fn micros(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Writes the traffic of a connection to a session file as it happens
+pub struct Recorder {
+    file: BufWriter<File>,
+    started: SystemTime,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, port: &str) -> io::Result<Recorder> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let started = SystemTime::now();
+        let mut file = BufWriter::new(File::create(path)?);
+        let header = Header { version: VERSION, started_us: micros(started), port: port.to_string() };
+        writeln!(file, "{}", serde_json::to_string(&header)?)?;
+        file.flush()?;
+
+        Ok(Recorder { file, started })
+    }
+
+    // Flushed right away, so a crash doesn't lose the end of a session
+    pub fn write(&mut self, chunk: &Chunk) -> io::Result<()> {
+        let record = Record {
+            us: micros(chunk.time).saturating_sub(micros(self.started)),
+            dir: if chunk.direction == Direction::Tx { "tx" } else { "rx" }.to_string(),
+            hex: chunk.data.iter().map(|x| format!("{:02x}", x)).collect(),
+        };
+        writeln!(self.file, "{}", serde_json::to_string(&record)?)?;
+        self.file.flush()
+    }
+}
+
+// A session file read back
+pub struct Recording {
+    pub port: String,
+    pub chunks: Vec<Chunk>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> io::Result<Recording> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+
+        let header: Header = serde_json::from_str(&lines.next().ok_or_else(|| invalid("empty session file".to_string()))??)?;
+        if header.version != VERSION {
+            return Err(invalid(format!("unsupported session file version {}", header.version)));
+        }
+        let started = UNIX_EPOCH + Duration::from_micros(header.started_us);
+
+        let mut chunks = vec![];
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let record: Record = serde_json::from_str(&line)?;
+            let direction = match record.dir.as_str() {
+                "rx" => Direction::Rx,
+                "tx" => Direction::Tx,
+                other => return Err(invalid(format!("unknown direction \"{}\"", other))),
+            };
+            let data = (0..record.hex.len())
+                .step_by(2)
+                .map(|i| record.hex.get(i..i + 2).and_then(|x| u8::from_str_radix(x, 16).ok()))
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(|| invalid(format!("invalid hex \"{}\"", record.hex)))?;
+
+            chunks.push(Chunk { time: started + Duration::from_micros(record.us), direction, data });
+        }
+
+        Ok(Recording { port: header.port, chunks })
+    }
+
+    // pcapng with one packet per chunk, the direction is in the packet
+    // flags
+    pub fn export_pcapng(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        // section header: byte order magic, version 1.0, unknown length
+        let mut body = vec![];
+        body.extend(0x1a2b3c4du32.to_le_bytes());
+        body.extend(1u16.to_le_bytes());
+        body.extend(0u16.to_le_bytes());
+        body.extend((-1i64).to_le_bytes());
+        write_block(&mut file, 0x0a0d0d0a, &body)?;
+
+        // interface description, named after the port, microsecond timestamps
+        let mut body = vec![];
+        body.extend(PCAPNG_LINKTYPE.to_le_bytes());
+        body.extend(0u16.to_le_bytes());
+        body.extend(0u32.to_le_bytes());
+        push_option(&mut body, 2, self.port.as_bytes());
+        push_option(&mut body, 0, &[]);
+        write_block(&mut file, 1, &body)?;
+
+        for chunk in self.chunks.iter() {
+            let time = micros(chunk.time);
+            let mut body = vec![];
+            body.extend(0u32.to_le_bytes());
+            body.extend(((time >> 32) as u32).to_le_bytes());
+            body.extend((time as u32).to_le_bytes());
+            body.extend((chunk.data.len() as u32).to_le_bytes());
+            body.extend((chunk.data.len() as u32).to_le_bytes());
+            body.extend(&chunk.data);
+            pad(&mut body);
+            // epb_flags: 1 inbound, 2 outbound
+            let flags: u32 = if chunk.direction == Direction::Tx { 2 } else { 1 };
+            push_option(&mut body, 2, &flags.to_le_bytes());
+            push_option(&mut body, 0, &[]);
+            write_block(&mut file, 6, &body)?;
+        }
+
+        file.flush()
+    }
+}
+
+fn pad(data: &mut Vec<u8>) {
+    while !data.len().is_multiple_of(4) {
+        data.push(0);
+    }
+}
+
+fn push_option(body: &mut Vec<u8>, code: u16, value: &[u8]) {
+    body.extend(code.to_le_bytes());
+    body.extend((value.len() as u16).to_le_bytes());
+    body.extend(value);
+    pad(body);
+}
+
+fn write_block(file: &mut impl Write, kind: u32, body: &[u8]) -> io::Result<()> {
+    let length = (body.len() as u32 + 12).to_le_bytes();
+    file.write_all(&kind.to_le_bytes())?;
+    file.write_all(&length)?;
+    file.write_all(body)?;
+    file.write_all(&length)
+}
+
+// Plays the board's side of a recording. What the host writes has to match
+// what was sent in the recording, the board's answers follow with their
+// recorded delays. Once the recording is over the board stays silent.
+pub struct Replay {
+    chunks: VecDeque<Chunk>,
+    // board output that is due
+    rx: VecDeque<u8>,
+    // host bytes the recording expects next
+    tx: VecDeque<u8>,
+    // when and for which recorded time the host last caught up
+    anchor: (Instant, SystemTime),
+    timeout: Duration,
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Replay {
+        let started = recording.chunks.first().map(|x| x.time).unwrap_or(UNIX_EPOCH);
+        Replay {
+            chunks: recording.chunks.into(),
+            rx: VecDeque::new(),
+            tx: VecDeque::new(),
+            anchor: (Instant::now(), started),
+            timeout: Duration::from_millis(100),
+        }
+    }
+
+    // When the next board chunk is due, None while the host still has to
+    // send something first
+    fn next_due(&mut self) -> Option<Instant> {
+        if self.tx.is_empty() {
+            if let Some(chunk) = self.chunks.pop_front() {
+                if chunk.direction == Direction::Tx {
+                    self.anchor.1 = chunk.time;
+                    self.tx.extend(chunk.data);
+                } else {
+                    self.chunks.push_front(chunk);
+                }
+            }
+        }
+
+        let chunk = self.chunks.front().filter(|_| self.tx.is_empty())?;
+        let delay = chunk.time.duration_since(self.anchor.1).unwrap_or_default();
+        Some(self.anchor.0 + delay)
+    }
+}
+
+impl Transport for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = Instant::now() + self.timeout;
+
+        while self.rx.is_empty() {
+            let now = Instant::now();
+            match self.next_due() {
+                Some(due) if due <= now => {
+                    if let Some(chunk) = self.chunks.pop_front() {
+                        self.anchor = (due, chunk.time);
+                        self.rx.extend(chunk.data);
+                    }
+                }
+                Some(due) if due < deadline => thread::sleep(due - now),
+                _ => {
+                    if now < deadline {
+                        thread::sleep(deadline - now);
+                    }
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+            }
+        }
+
+        let n = buf.len().min(self.rx.len());
+        for (dst, src) in buf.iter_mut().zip(self.rx.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            // the host didn't wait for output the board sent before, it
+            // is delivered right away
+            while self.tx.is_empty() {
+                match self.chunks.pop_front() {
+                    Some(chunk) if chunk.direction == Direction::Tx => {
+                        self.anchor.1 = chunk.time;
+                        self.tx.extend(chunk.data);
+                    }
+                    Some(chunk) => self.rx.extend(chunk.data),
+                    None => return Err(invalid(format!("recording is over, got {:?}", byte as char))),
+                }
+            }
+
+            let expected = self.tx.pop_front().unwrap_or_default();
+            if byte != expected {
+                return Err(invalid(format!("expected {:?}, got {:?}", expected as char, byte as char)));
+            }
+        }
+
+        // the board's next output is timed from the end of what the host
+        // sent
+        if self.tx.is_empty() {
+            self.anchor.0 = Instant::now();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
@@ -0,0 +1,791 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use super::transport::{MemoryTransport, Transport};
+
+// Entry shown in the port list for the built-in simulator
+pub const DEMO_PORT: &str = "demo board";
+
+const VERSION: &str = "0.5.0";
+const MAXLED: u16 = 18;
+const MAXIDENTIFIER: usize = 32;
+const INPUTS: usize = 4;
+const ACTORS: usize = 2;
+
+const MAIN_PROMPT: &str = "\nMain Menu\n\n(1) wifi settings\n(2) webserver settings\n(3) swarm settings\n(4) alias names\n(5) factory settings\n\n(0) exit\nmain>";
+const RESTART_PROMPT: &str = "To apply your changes, the device needs to be restarted.\nSave settings and restart now (Y/N)?";
+const SWARM_COMMUNICATION: [&str; 4] = ["none", "wifi", "RS485", "wifi & RS485"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WifiMode {
+    Off,
+    Ap,
+    Client,
+}
+
+// Non-volatile settings of a simulated ftSwarm (JST)
+#[derive(Debug, Clone)]
+pub struct Nvs {
+    pub serial_number: u16,
+    pub rs485: bool,
+    pub wifi_mode: WifiMode,
+    pub ssid: String,
+    pub password: String,
+    pub channel: u16,
+    pub web_ui: bool,
+    pub rgb_leds: u16,
+    pub swarm_name: String,
+    pub swarm_pin: u16,
+    pub swarm_communication: u16,
+    // alias of the controller itself, shown as its hostname
+    pub hostname: String,
+    // port name (A1, M2, LED1, SERVO, ...) -> alias
+    pub aliases: BTreeMap<String, String>,
+}
+
+impl Nvs {
+    pub fn factory(serial_number: u16) -> Nvs {
+        Nvs {
+            serial_number,
+            rs485: false,
+            wifi_mode: WifiMode::Ap,
+            ssid: format!("ftSwarm{}", serial_number),
+            password: String::new(),
+            channel: 1,
+            web_ui: true,
+            rgb_leds: 2,
+            swarm_name: format!("ftSwarm{}", serial_number),
+            swarm_pin: serial_number,
+            swarm_communication: 1,
+            hostname: String::new(),
+            aliases: BTreeMap::new(),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        format!("ftSwarm{}", self.serial_number)
+    }
+
+    pub fn host(&self) -> String {
+        if self.hostname.is_empty() {
+            self.name()
+        } else {
+            self.hostname.clone()
+        }
+    }
+
+    // port names in the order the alias menu lists them
+    pub fn ports(&self) -> Vec<String> {
+        let mut ports = vec![];
+        ports.extend((1..=INPUTS).map(|i| format!("A{}", i)));
+        ports.extend((1..=ACTORS).map(|i| format!("M{}", i)));
+        ports.extend((1..=self.rgb_leds).map(|i| format!("LED{}", i)));
+        ports.push("SERVO".to_string());
+        ports
+    }
+
+    fn alias(&self, port: &str) -> &str {
+        self.aliases.get(port).map(|x| x.as_str()).unwrap_or("")
+    }
+
+    fn resolve(&self, name: &str) -> Option<String> {
+        self.ports()
+            .into_iter()
+            .find(|port| port == name || (!name.is_empty() && self.alias(port) == name))
+    }
+}
+
+// Hardware state of a simulated board, shared with the UI
+#[derive(Debug, Default)]
+pub struct BoardIo {
+    // input port -> raw value, switches count as pressed when non-zero
+    pub inputs: BTreeMap<String, i32>,
+    // actor port -> speed
+    pub outputs: BTreeMap<String, i32>,
+    pub leds: bool,
+}
+
+// Emulates an ftSwarm running the board sketch (src/main.cpp): the sketch
+// commands, subscriptions and the ftSwarm.setup() menus.
+#[derive(Clone)]
+pub struct Simulator {
+    pub flash: Arc<Mutex<Nvs>>,
+    pub io: Arc<Mutex<BoardIo>>,
+    // swarms other simulated controllers have formed, joining them succeeds
+    pub swarms_in_range: Arc<Mutex<Vec<String>>>,
+    pub boot_delay: Duration,
+}
+
+impl Simulator {
+    pub fn new(serial_number: u16) -> Simulator {
+        let io = BoardIo {
+            inputs: (1..=INPUTS).map(|i| (format!("A{}", i), 0)).collect(),
+            outputs: (1..=ACTORS).map(|i| (format!("M{}", i), 0)).collect(),
+            leds: false,
+        };
+
+        Simulator {
+            flash: Arc::new(Mutex::new(Nvs::factory(serial_number))),
+            io: Arc::new(Mutex::new(io)),
+            swarms_in_range: Arc::new(Mutex::new(vec![])),
+            boot_delay: Duration::from_millis(300),
+        }
+    }
+
+    // Another board in radio range of this one, they can join each other's swarms
+    pub fn neighbour(&self, serial_number: u16) -> Simulator {
+        Simulator { swarms_in_range: self.swarms_in_range.clone(), ..Simulator::new(serial_number) }
+    }
+
+    // Powers up a board and returns the host's end of its serial line.
+    // The board stops once that end is dropped.
+    pub fn connect(&self) -> MemoryTransport {
+        let (host, board) = MemoryTransport::pair();
+        self.run(board);
+        host
+    }
+
+    pub fn run<T: Transport + 'static>(&self, transport: T) -> thread::JoinHandle<()> {
+        let simulator = self.clone();
+        thread::spawn(move || {
+            let live = simulator.flash.lock().unwrap().clone();
+            let mut board = Board {
+                transport,
+                rx: VecDeque::new(),
+                live,
+                simulator,
+                nodes: vec![],
+                triggers: vec![],
+                members: 1,
+            };
+            board.run();
+        })
+    }
+}
+
+enum Halt {
+    Restart,
+    Closed,
+}
+
+type Step<T> = Result<T, Halt>;
+
+#[derive(Clone, Copy)]
+enum Node {
+    Digital { state: Option<bool> },
+    Analog { value: u16, threshold: u16 },
+}
+
+struct Subscription {
+    name: String,
+    port: Option<String>,
+    node: Node,
+}
+
+struct OnTrigger {
+    input: Option<String>,
+    up: bool,
+    output: Option<String>,
+    value: i32,
+    state: bool,
+}
+
+struct Board<T: Transport> {
+    transport: T,
+    rx: VecDeque<u8>,
+    live: Nvs,
+    simulator: Simulator,
+    nodes: Vec<Subscription>,
+    triggers: Vec<OnTrigger>,
+    members: u16,
+}
+
+// Arduino String::toInt()
+fn to_int(s: &str) -> i64 {
+    let s = s.trim_start();
+    let (sign, digits) = match s.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, s.strip_prefix('+').unwrap_or(s)),
+    };
+    let digits: String = digits.chars().take_while(|x| x.is_ascii_digit()).collect();
+    digits.parse::<i64>().map(|x| sign * x).unwrap_or(0)
+}
+
+// Arduino String::substring(from), clamped to the string length
+fn substring(s: &str, from: usize) -> &str {
+    s.get(from.min(s.len())..).unwrap_or("")
+}
+
+impl<T: Transport> Board<T> {
+    fn run(&mut self) {
+        loop {
+            let result = self.boot().and_then(|_| self.sketch());
+            match result {
+                Err(Halt::Restart) => continue,
+                Ok(()) | Err(Halt::Closed) => return,
+            }
+        }
+    }
+
+    fn out(&mut self, text: &str) {
+        // a closed line is noticed by the next read
+        let _ = self.transport.write_all(text.as_bytes());
+    }
+
+    // Reads whatever arrives within the timeout into the receive buffer
+    fn fill(&mut self, timeout: Duration) -> Step<bool> {
+        let mut buffer = [0; 256];
+        let _ = self.transport.set_timeout(timeout);
+
+        match self.transport.read(&mut buffer) {
+            Ok(0) => Err(Halt::Closed),
+            Ok(n) => {
+                self.rx.extend(&buffer[..n]);
+                Ok(true)
+            }
+            Err(e) if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(_) => Err(Halt::Closed),
+        }
+    }
+
+    fn read_byte(&mut self) -> Step<u8> {
+        loop {
+            if let Some(byte) = self.rx.pop_front() {
+                return Ok(byte);
+            }
+            self.fill(Duration::from_millis(25))?;
+        }
+    }
+
+    fn restart(&mut self) -> Step<()> {
+        Err(Halt::Restart)
+    }
+
+    fn boot(&mut self) -> Step<()> {
+        self.rx.clear();
+        self.live = self.simulator.flash.lock().unwrap().clone();
+        self.nodes.clear();
+        self.triggers.clear();
+        self.members = 1;
+        {
+            let mut io = self.simulator.io.lock().unwrap();
+            io.outputs.values_mut().for_each(|x| *x = 0);
+            io.leds = false;
+        }
+
+        self.out("ESP-ROM:esp32s3-20210327\r\n");
+        thread::sleep(self.simulator.boot_delay);
+
+        self.out("Setup: Executing on core 1\r\n");
+        self.out(&format!(
+            "\n\nftSwarmOS {}\n\n(C) Christian Bergschneider & Stefan Fuss\n\nPress any key to enter bios settings.\n",
+            VERSION
+        ));
+        let boot = format!("Boot {} (SN:{}).\n", self.live.host(), self.live.serial_number);
+        self.out(&boot);
+
+        if self.live.wifi_mode != WifiMode::Off {
+            if self.live.ssid.is_empty() {
+                self.out("Invalid wifi configuration found. Starting AP mode.\n");
+                self.live.ssid = self.live.host();
+                self.live.wifi_mode = WifiMode::Ap;
+            }
+
+            let ip = if self.live.wifi_mode == WifiMode::Ap {
+                let text = format!("Create own SSID: {}\n", self.live.host());
+                self.out(&text);
+                "192.168.4.1".to_string()
+            } else {
+                let text = format!("Attempting to connect to SSID: {}connected!\n", self.live.ssid);
+                self.out(&text);
+                format!("192.168.1.{}", 2 + self.live.serial_number % 250)
+            };
+            let text = format!("hostname: {}\nip-address: {}\n", self.live.host(), ip);
+            self.out(&text);
+        }
+
+        self.out("Start normal operation.\n");
+        self.out(">>>\r\n");
+        Ok(())
+    }
+
+    // loop() of the board sketch
+    fn sketch(&mut self) -> Step<()> {
+        loop {
+            // delay(25)
+            self.fill(Duration::from_millis(25))?;
+
+            if !self.rx.is_empty() {
+                // timedRead(): a command ends when no char arrived for 3ms
+                while self.fill(Duration::from_millis(3))? {}
+                let bytes: Vec<u8> = self.rx.drain(..).collect();
+                let command = String::from_utf8_lossy(&bytes).replace(['\r', '\n'], "");
+                self.command(&command)?;
+            }
+
+            self.inputs();
+        }
+    }
+
+    fn command(&mut self, command: &str) -> Step<()> {
+        if let Some(rest) = command.strip_prefix("sub") {
+            let rest = substring(rest, 1);
+
+            if rest.starts_with("digital") {
+                let name = substring(rest, 8).to_string();
+                let port = self.wait_for(&name)?;
+                self.nodes.push(Subscription { name: name.clone(), port, node: Node::Digital { state: None } });
+                self.out(&format!("#debug Subscribed to Button Press on {}\r\n", name));
+                self.out("suc sub\r\n");
+                return Ok(());
+            } else if rest.starts_with("analog") {
+                let rest = substring(rest, 7);
+                let (threshold, name) = rest.split_once(' ').unwrap_or(("", rest));
+                let threshold = to_int(threshold) as u16;
+                let name = name.to_string();
+                let port = self.wait_for(&name)?;
+                self.nodes.push(Subscription { name: name.clone(), port, node: Node::Analog { value: 0, threshold } });
+                self.out(&format!("#debug Subscribed to Analog Input on {}\r\n", name));
+                self.out("suc sub\r\n");
+                return Ok(());
+            }
+
+            self.out("err sub\r\n");
+        } else if command.starts_with("mot") {
+            let rest = substring(command, 4);
+            let (name, value) = rest.split_once(' ').unwrap_or((rest, rest));
+            let value = to_int(value);
+            if let Some(port) = self.wait_for(name)? {
+                self.simulator.io.lock().unwrap().outputs.insert(port, value as i16 as i32);
+            }
+            self.out(&format!("suc mot {}\r\n", value));
+        } else if command.starts_with("led") {
+            self.simulator.io.lock().unwrap().leds = command.starts_with("led on");
+            self.out("suc led\r\n");
+        } else if command.starts_with("otr") {
+            let mut parts = substring(command, 4).split(' ').filter(|x| !x.is_empty());
+            let input = parts.next().unwrap_or("").to_string();
+            let up = to_int(parts.next().unwrap_or("")) != 0;
+            let output = parts.next().unwrap_or("").to_string();
+            let value = to_int(parts.next().unwrap_or("")) as i32;
+
+            let input = self.wait_for(&input)?;
+            let output = self.wait_for(&output)?;
+            let state = self.input_value(&input) != 0;
+            self.triggers.push(OnTrigger { input, up, output, value, state });
+            self.out("suc otr\r\n");
+        } else if command.starts_with("nod") {
+            if self.nodes.is_empty() {
+                self.out("#debug nodes = []\r\n");
+            } else {
+                self.out("#debug nodes = [\r\n");
+                let names: Vec<String> = self.nodes.iter().map(|x| x.name.clone()).collect();
+                for name in names {
+                    self.out(&format!("#debug '{}',\r\n", name));
+                }
+                self.out("#debug ]\r\n");
+            }
+            self.out("suc nod\r\n");
+        } else if command.starts_with("stp") {
+            self.setup()?;
+            self.out("suc stp\r\n");
+        } else if command.starts_with("res") {
+            return self.restart();
+        }
+
+        Ok(())
+    }
+
+    // SwOSSwarm::_waitFor(), gives up once any key is pressed
+    fn wait_for(&mut self, name: &str) -> Step<Option<String>> {
+        if let Some(port) = self.live.resolve(name) {
+            return Ok(Some(port));
+        }
+
+        self.out(&format!(
+            "Waiting on device {}. Press anykey to enter setup and change remote control settings.\n",
+            name
+        ));
+        while self.rx.is_empty() {
+            self.fill(Duration::from_millis(25))?;
+        }
+        while self.fill(Duration::from_millis(25))? {}
+        self.rx.clear();
+        Ok(None)
+    }
+
+    fn input_value(&self, port: &Option<String>) -> i32 {
+        let io = self.simulator.io.lock().unwrap();
+        port.as_ref().and_then(|x| io.inputs.get(x)).copied().unwrap_or(0)
+    }
+
+    fn inputs(&mut self) {
+        for i in 0..self.triggers.len() {
+            let state = self.input_value(&self.triggers[i].input) != 0;
+            let trigger = &mut self.triggers[i];
+            if state != trigger.state {
+                trigger.state = state;
+                if state == trigger.up {
+                    if let Some(output) = &trigger.output {
+                        self.simulator.io.lock().unwrap().outputs.insert(output.clone(), trigger.value);
+                    }
+                }
+            }
+        }
+
+        for i in 0..self.nodes.len() {
+            let actual = self.input_value(&self.nodes[i].port);
+            let node = &mut self.nodes[i];
+
+            let line = match &mut node.node {
+                Node::Digital { state } => {
+                    let pressed = actual != 0;
+                    if *state == Some(pressed) {
+                        continue;
+                    }
+                    *state = Some(pressed);
+                    format!("!{} {}\r\n", node.name, pressed as u8)
+                }
+                Node::Analog { value, threshold } => {
+                    let actual = actual.clamp(0, u16::MAX as i32) as u16;
+                    if actual.abs_diff(*value) < *threshold {
+                        continue;
+                    }
+                    *value = actual;
+                    format!("!{} {}\r\n", node.name, actual)
+                }
+            };
+            self.out(&line);
+        }
+    }
+
+    // easyKey.cpp
+    fn enter_something(&mut self, prompt: &str, size: usize, hidden: bool, valid: fn(u8) -> bool) -> Step<Option<String>> {
+        let mut s = String::new();
+        self.out(prompt);
+
+        loop {
+            let ch = self.read_byte()?;
+            match ch {
+                b'\n' | b'\r' => {
+                    self.out("\n");
+                    return Ok(Some(s));
+                }
+                8 | 127 => {
+                    if s.pop().is_some() {
+                        self.transport.write_all(&[127]).ok();
+                    }
+                }
+                27 => {
+                    self.out("\n");
+                    return Ok(None);
+                }
+                _ => {
+                    if ch < 255 && valid(ch) && s.len() < size - 1 {
+                        self.transport.write_all(&[if hidden { b'*' } else { ch }]).ok();
+                        s.push(ch as char);
+                    }
+                }
+            }
+        }
+    }
+
+    fn enter_string(&mut self, prompt: &str, size: usize, hidden: bool) -> Step<String> {
+        let value = self.enter_something(prompt, size, hidden, |x| (32..=126).contains(&x))?;
+        Ok(value.unwrap_or_default())
+    }
+
+    fn enter_identifier(&mut self, prompt: &str, size: usize) -> Step<String> {
+        let value = self.enter_something(prompt, size, false, |x| x.is_ascii_alphanumeric())?;
+        Ok(value.unwrap_or_default())
+    }
+
+    fn enter_number(&mut self, prompt: &str, default: u16, min: u16, max: u16) -> Step<u16> {
+        loop {
+            let value = match self.enter_something(prompt, 6, false, |x| x.is_ascii_digit())? {
+                Some(s) if !s.is_empty() => s.parse::<u32>().unwrap_or(0) as u16,
+                _ => default,
+            };
+
+            if value >= min && value <= max {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn yes_no(&mut self, prompt: &str) -> Step<bool> {
+        let value = self.enter_something(prompt, 2, false, |x| matches!(x, b'Y' | b'y' | b'N' | b'n'))?;
+        Ok(matches!(value.as_deref(), Some("y") | Some("Y")))
+    }
+
+    // nvs.save(), aliases are stored separately
+    fn save(&mut self) {
+        let mut flash = self.simulator.flash.lock().unwrap();
+        let hostname = flash.hostname.clone();
+        let aliases = flash.aliases.clone();
+        *flash = Nvs { hostname, aliases, ..self.live.clone() };
+    }
+
+    fn save_aliases(&mut self) {
+        let mut flash = self.simulator.flash.lock().unwrap();
+        flash.hostname = self.live.hostname.clone();
+        flash.aliases = self.live.aliases.clone();
+    }
+
+    fn save_and_restart(&mut self) -> Step<()> {
+        self.save();
+        self.restart()
+    }
+
+    // FtSwarm::setup()
+    fn setup(&mut self) -> Step<()> {
+        self.out(&format!("\n\nftSwarmOS {}\n\n(C) Christian Bergschneider & Stefan Fuss\n", VERSION));
+
+        loop {
+            match self.enter_number(MAIN_PROMPT, 0, 0, 5)? {
+                0 => return Ok(()),
+                1 => self.wifi_menu()?,
+                2 => self.web_server_menu()?,
+                3 => self.swarm_menu()?,
+                4 => self.alias_menu()?,
+                _ => self.factory_settings()?,
+            }
+        }
+    }
+
+    fn exit_menu(&mut self, changed: bool) -> Step<()> {
+        if changed && self.yes_no(RESTART_PROMPT)? {
+            return self.save_and_restart();
+        }
+        Ok(())
+    }
+
+    fn wifi_menu(&mut self) -> Step<()> {
+        let mut changed = false;
+
+        loop {
+            self.out("wifi settings\n\n");
+
+            let (prompt, max) = match self.live.wifi_mode {
+                WifiMode::Off => ("(1) wifi: off\n\n(0) exit\nwifi>".to_string(), 1),
+                WifiMode::Ap => (
+                    format!(
+                        "(1) wifi:     AP-MODE\n(2) SSID:     {}\n(X) Password: NOPASSWORD\n(4) Channel:  {}\n\n(0) exit\nwifi>",
+                        self.live.ssid, self.live.channel
+                    ),
+                    4,
+                ),
+                WifiMode::Client => (
+                    format!(
+                        "(1) wifi:     CLIENT-MODE\n(2) SSID:     {}\n(3) Password: ******\n\n(0) exit\nwifi>",
+                        self.live.ssid
+                    ),
+                    3,
+                ),
+            };
+
+            match self.enter_number(&prompt, 0, 0, max)? {
+                0 => return self.exit_menu(changed),
+                1 => {
+                    let current = self.live.wifi_mode as u16;
+                    let mode = self.enter_number("enter wifi mode [ 0-off , 1-AP-Mode, 2-Client-Mode]: ", current, 0, 2)?;
+                    if mode != current {
+                        if mode == 0 && self.live.swarm_communication & 0x1 != 0 {
+                            self.out("Error: please deactivate wifi in swarm communication first.\n");
+                        } else {
+                            self.live.wifi_mode = [WifiMode::Off, WifiMode::Ap, WifiMode::Client][mode as usize];
+                            if self.live.wifi_mode == WifiMode::Ap && !(1..=13).contains(&self.live.channel) {
+                                self.live.channel = 1;
+                            }
+                            changed = true;
+                        }
+                    }
+                }
+                2 => {
+                    changed = true;
+                    self.live.ssid = self.enter_string("Please enter new SSID: ", 64, false)?;
+                }
+                3 => {
+                    changed = true;
+                    self.live.password = self.enter_string("Please enter new Password: ", 64, true)?;
+                }
+                _ => {
+                    changed = true;
+                    let channel = self.live.channel;
+                    self.live.channel = self.enter_number("enter channel [1..13] - use 1,6 or 11 if possible: ", channel, 1, 13)?;
+                }
+            }
+        }
+    }
+
+    fn web_server_menu(&mut self) -> Step<()> {
+        let mut changed = false;
+
+        loop {
+            self.out("web server settings\n\n");
+
+            let on_off = if self.live.web_ui { "on" } else { "off" };
+            let (prompt, max) = if self.live.web_ui {
+                (format!("(1) WebUI: {}\n(2) Show {} ftPixels in UI\n\n(0) exit\nweb server>", on_off, self.live.rgb_leds), 2)
+            } else {
+                (format!("(1) WebUI: {}\n\n(0) exit\nweb server>", on_off), 1)
+            };
+
+            match self.enter_number(&prompt, 0, 0, max)? {
+                0 => return self.exit_menu(changed),
+                1 => {
+                    changed = true;
+                    self.live.web_ui = !self.live.web_ui;
+                }
+                _ => {
+                    changed = true;
+                    let leds = self.live.rgb_leds;
+                    self.live.rgb_leds = self.enter_number("enter number of ftPixel in WebUI [2..18]: ", leds, 2, MAXLED)?;
+                }
+            }
+        }
+    }
+
+    fn join_swarm(&mut self, create: bool) -> Step<()> {
+        let name = loop {
+            let name = self.enter_string("Please enter new swarm's name [minimum 5 chars]: ", MAXIDENTIFIER, false)?;
+            if name != self.live.swarm_name && name.len() > 4 {
+                break name;
+            }
+        };
+
+        let pin = self.enter_number("Please enter new swarm's PIN [1..9999]: ", 0, 1, 9999)?;
+
+        let prompt = format!(
+            "Do you really want to quit swarm \"{}\" and {} swarm \"{}\" with pin {} (Y/N) ?",
+            self.live.swarm_name,
+            if create { "create new" } else { "join" },
+            name,
+            pin
+        );
+        if !self.yes_no(&prompt)? {
+            return Ok(());
+        }
+
+        // pairing and registering
+        thread::sleep(Duration::from_millis(500));
+
+        let mut in_range = self.simulator.swarms_in_range.lock().unwrap();
+        let found = in_range.contains(&name);
+        if create && !found {
+            in_range.push(name.clone());
+        }
+        drop(in_range);
+
+        if create || found {
+            self.live.swarm_name = name.clone();
+            self.live.swarm_pin = pin;
+            self.members = if create { 1 } else { 2 };
+            self.save();
+            let verb = if create { "created" } else { "joined" };
+            self.out(&format!("Swarm \"{}\" {} sucessfully.\n", name, verb));
+        } else {
+            let text = format!("ERROR: swarm \"{}\" not found. Rejoined old swarm {}\n", name, self.live.swarm_name);
+            self.out(&text);
+        }
+
+        Ok(())
+    }
+
+    fn swarm_menu(&mut self) -> Step<()> {
+        loop {
+            let text = format!(
+                "\nSwarm menu\n\nThis device is connected to swarm \"{}\" with {} member(s) online.\nSwarm PIN is {}.\n",
+                self.live.swarm_name, self.members, self.live.swarm_pin
+            );
+            self.out(&text);
+            let prompt = format!(
+                "({}) swarm communication: {}\n(2) create a new swarm\n(3) join another swarm\n(4) list swarm members\n\n(0) main\nswarm>",
+                if self.live.rs485 { "1" } else { "X" },
+                SWARM_COMMUNICATION[self.live.swarm_communication as usize % 4]
+            );
+
+            match self.enter_number(&prompt, 0, 0, 4)? {
+                0 => return Ok(()),
+                1 => {
+                    if self.live.rs485 {
+                        let current = self.live.swarm_communication;
+                        let communication = self.enter_number("enter swarm communication [1-wifi, 2-RS485, 3-both]:", current, 1, 3)?;
+                        if communication != current {
+                            if self.live.wifi_mode == WifiMode::Off && communication & 0x1 != 0 {
+                                self.out("Error: please activate wifi first.\n");
+                            } else {
+                                self.live.swarm_communication = communication;
+                                if self.yes_no(RESTART_PROMPT)? {
+                                    self.save_and_restart()?;
+                                }
+                            }
+                        }
+                    }
+                }
+                2 => self.join_swarm(true)?,
+                3 => self.join_swarm(false)?,
+                _ => {
+                    self.out("\nSwarm members:\n");
+                    let text = format!("#0 {}\n", self.live.host());
+                    self.out(&text);
+                    for i in 1..self.members {
+                        self.out(&format!("#{} ftSwarm{}\n", i, self.live.serial_number as u32 + i as u32));
+                    }
+                }
+            }
+        }
+    }
+
+    fn alias_menu(&mut self) -> Step<()> {
+        let mut changed = false;
+
+        loop {
+            self.out("alias controler menu:\n\n");
+
+            let ports = self.live.ports();
+            let text = format!("({:2}) hostname {} - {}\n", 1, self.live.name(), self.live.hostname);
+            self.out(&text);
+            for (i, port) in ports.iter().enumerate() {
+                let text = format!("({:2}) {:<4} - {:<32}\n", i + 2, port, self.live.alias(port));
+                self.out(&text);
+            }
+
+            let choice = self.enter_number("\n(0) exit\nalias>", 0, 0, ports.len() as u16 + 1)? as usize;
+
+            if choice == 0 {
+                if changed && self.yes_no("Save changes? (Y/N)?")? {
+                    self.save_aliases();
+                }
+                return Ok(());
+            }
+
+            let name = if choice == 1 { self.live.name() } else { ports[choice - 2].clone() };
+            let alias = self.enter_identifier(&format!("{} - please enter new alias: ", name), MAXIDENTIFIER)?;
+            if choice == 1 {
+                self.live.hostname = alias;
+            } else {
+                self.live.aliases.insert(name, alias);
+            }
+            changed = true;
+        }
+    }
+
+    fn factory_settings(&mut self) -> Step<()> {
+        if self.yes_no("Do you want to reset this device to it's factory settings (Y/N)?")? {
+            self.live = Nvs {
+                rs485: self.live.rs485,
+                web_ui: self.live.web_ui,
+                swarm_communication: self.live.swarm_communication,
+                ..Nvs::factory(self.live.serial_number)
+            };
+            self.out("device will restart now.\n");
+            thread::sleep(Duration::from_millis(200));
+            self.save_aliases();
+            return self.save_and_restart();
+        }
+        Ok(())
+    }
+}
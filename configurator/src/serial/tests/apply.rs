@@ -0,0 +1,127 @@
+use super::*;
+use crate::serial::expect::{Expect, Output, BOOT_TIMEOUT, PROMPT_TIMEOUT};
+use crate::serial::menu::{self, BoardConfig};
+use crate::serial::simulator::{WifiMode, DEMO_PORT};
+use crate::serial::Profile;
+
+#[test]
+fn apply_read_and_verify_through_the_menus() {
+    let simulator = simulator();
+    let mut host = simulator.connect();
+    let mut output = |_: Output| {};
+    let mut expect = Expect::new(&mut host, &mut output);
+    expect.expect(">>>", BOOT_TIMEOUT).unwrap();
+
+    let target = settings();
+    let checks = menu::apply(&mut expect, &target, false).unwrap();
+    let failed: Vec<_> = checks.iter().filter(|x| !x.passed()).collect();
+    assert!(failed.is_empty(), "{:?}", failed);
+
+    let flash = simulator.flash.lock().unwrap().clone();
+    assert_eq!(flash.wifi_mode, WifiMode::Client);
+    assert_eq!(flash.ssid, "workshop");
+    assert_eq!(flash.password, "secret42");
+    assert_eq!(flash.rgb_leds, 3);
+    assert_eq!(flash.swarm_name, "factory");
+    assert_eq!(flash.swarm_pin, 42);
+    assert_eq!(flash.hostname, "robot1");
+    assert_eq!(flash.aliases.get("A2").map(|x| x.as_str()), Some("stop"));
+    assert_eq!(flash.aliases.get("SERVO").map(|x| x.as_str()), Some("arm"));
+
+    // the password and how the swarm was formed aren't shown, they come
+    // from the base
+    let read = menu::read(&mut expect, &Settings::default()).unwrap();
+    assert_eq!(read.password, Settings::default().password);
+    assert_eq!(json(&Settings { password: target.password.clone(), ..read }), json(&target));
+
+    // nothing differs, so nothing is written
+    let checks = menu::apply(&mut expect, &target, false).unwrap();
+    assert!(checks.iter().all(|x| x.passed()));
+
+    let checks = menu::apply(&mut expect, &target, true).unwrap();
+    assert!(checks.iter().all(|x| x.passed()));
+}
+
+#[test]
+fn verify_reports_each_difference() {
+    let target = settings();
+    let board = Settings {
+        hostname: "robot2".to_string(),
+        rgb_led_num: 2,
+        led_ports: vec!["lamp".to_string(), String::new()],
+        ..target.clone()
+    };
+    let current = BoardConfig { settings: board, client_mode: true, web_ui: true };
+
+    let checks = menu::verify(&current, &target);
+    assert!(checks.iter().all(|x| !x.expected.contains(&target.password)));
+    let failed: Vec<_> = checks.into_iter().filter(|x| !x.passed()).collect();
+    let fields: Vec<_> = failed.iter().map(|x| x.field.as_str()).collect();
+    assert_eq!(fields, ["rgb led num", "hostname"]);
+    assert_eq!(failed[1].actual.as_deref(), Some("robot2"));
+
+    // LED3 only passes without an alias, as the board has two
+    let target = Settings { led_ports: vec!["lamp".to_string(), String::new(), "flash".to_string()], ..target };
+    let failed: Vec<_> = menu::verify(&current, &target).into_iter().filter(|x| !x.passed()).collect();
+    assert_eq!(failed.last().map(|x| (x.field.as_str(), x.actual.as_deref())), Some(("LED3", Some("(no such port)"))));
+}
+
+#[test]
+fn apply_and_read_through_the_worker() {
+    let mut session = Session::new();
+    assert_eq!(session.run(Command::Connect(DEMO_PORT.to_string(), Profile::default())), JobStatus::Succeeded);
+    session.wait_line(">>>");
+
+    let target = settings();
+    assert_eq!(session.run(Command::Apply(target.clone(), false)), JobStatus::Succeeded);
+    let checks = session.checks();
+    assert!(!checks.is_empty() && checks.iter().all(|x| x.passed()));
+
+    assert_eq!(session.run(Command::Read(target.clone())), JobStatus::Succeeded);
+    assert_eq!(json(&session.settings().unwrap()), json(&target));
+}
+
+#[test]
+fn apply_fails_when_the_board_differs() {
+    let mut session = Session::new();
+    let board = simulator();
+    session.run(Command::Attach("simulator".to_string(), Box::new(board.connect())));
+    session.wait_line(">>>");
+
+    // the simulated board is a JST one, the menus take everything but the
+    // check afterwards tells
+    let mut target = settings();
+    target.swarm_type = Type::RS485;
+    assert_eq!(session.run(Command::Apply(target.clone(), false)), JobStatus::Failed("board differs in swarm type".to_string()));
+    let failed: Vec<_> = session.checks().into_iter().filter(|x| !x.passed()).map(|x| x.field).collect();
+    assert_eq!(failed, ["swarm type"]);
+
+    // it has no A5 either
+    target.input_ports.extend(["left".to_string(), String::new()]);
+    let JobStatus::Failed(error) = session.run(Command::Apply(target, false)) else { panic!("apply succeeded") };
+    assert!(error.contains("no port A5"), "{}", error);
+}
+
+// The firmware keeps the pin of the swarm it is in, so a new pin alone is
+// refused instead of failing the check afterwards
+#[test]
+fn swarm_pin_alone_is_rejected() {
+    let simulator = simulator();
+    let mut host = simulator.connect();
+    let mut output = |_: Output| {};
+    let mut expect = Expect::new(&mut host, &mut output);
+    expect.expect(">>>", BOOT_TIMEOUT).unwrap();
+
+    let target = settings();
+    menu::apply(&mut expect, &target, false).unwrap();
+
+    let target = Settings { swarm_pin: "43".to_string(), ..target };
+    for full in [false, true] {
+        let error = menu::apply(&mut expect, &target, full).err().unwrap();
+        assert!(error.to_string().ends_with("the swarm pin can only change together with the swarm name"), "{}", error);
+        assert_eq!(simulator.flash.lock().unwrap().swarm_pin, 42);
+    }
+
+    // the menus were left behind
+    expect.send_expect("mot M1 10", "suc mot 10", PROMPT_TIMEOUT).unwrap();
+}
@@ -0,0 +1,46 @@
+use super::*;
+use crate::serial::transport::MemoryTransport;
+use crate::serial::ConnectOptions;
+
+// The board's end goes away, e.g. a bridge closed the connection: the
+// session notices on the next read and keeps the port to reconnect to
+#[test]
+fn lose_a_closed_connection() {
+    let mut session = Session::new();
+    session.worker.set_options(ConnectOptions { reconnect: true, ..ConnectOptions::default() });
+    let (host, board) = MemoryTransport::pair();
+    assert_eq!(session.run(Command::Attach("board".to_string(), Box::new(host))), JobStatus::Succeeded);
+    assert_eq!(session.snapshot().connected, Some("board".to_string()));
+
+    drop(board);
+    session.wait_line("lost connection to board");
+    let snapshot = session.snapshot();
+    assert_eq!(snapshot.connected, None);
+    assert_eq!(snapshot.lost, Some("board".to_string()));
+    assert!(snapshot.lost_device.is_none());
+    assert!(snapshot.last_error.is_some_and(|x| x.contains("connection closed")));
+
+    // commands fail until it is back
+    assert!(matches!(session.run(Command::Send("mot M1 10".to_string())), JobStatus::Failed(_)));
+}
+
+// Without rerun_startup the startup commands are sent once per connect
+#[test]
+fn startup_runs_once_after_connecting() {
+    let mut session = Session::new();
+    session.worker.set_options(ConnectOptions { startup: vec!["mot M1 10".to_string()], ..ConnectOptions::default() });
+    let board = simulator();
+    assert_eq!(session.run(Command::Attach("board".to_string(), Box::new(board.connect()))), JobStatus::Succeeded);
+    session.wait_line("suc mot 10");
+
+    // a restart doesn't bring them back
+    board.io.lock().unwrap().outputs.insert("M1".to_string(), 0);
+    assert_eq!(session.run(Command::Send("res".to_string())), JobStatus::Succeeded);
+    let deadline = Instant::now() + JOB_TIMEOUT;
+    while session.events.iter().filter(|x| matches!(x, Event::Line(line) if line.text == ">>>")).count() < 2 {
+        session.poll(deadline, "the restart");
+    }
+    thread::sleep(Duration::from_millis(1000));
+    session.poll(deadline, "events");
+    assert_eq!(board.io.lock().unwrap().outputs.get("M1"), Some(&0));
+}
@@ -0,0 +1,102 @@
+use super::*;
+use crate::serial::Job;
+
+impl Session {
+    // What the worker reported about job `id`, in order
+    fn job_events(&self, id: JobId) -> Vec<Job> {
+        self.events.iter().filter_map(|x| match x {
+            Event::Job(job) if job.id == id => Some(job.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    // Order in which the jobs started running
+    fn started(&self) -> Vec<JobId> {
+        self.events.iter().filter_map(|x| match x {
+            Event::Job(job) if job.status == JobStatus::Running && job.progress.is_none() => Some(job.id),
+            _ => None,
+        }).collect()
+    }
+
+    fn attach(&mut self) -> Simulator {
+        let board = simulator();
+        assert_eq!(self.run(Command::Attach("board".to_string(), Box::new(board.connect()))), JobStatus::Succeeded);
+        self.wait_line(">>>");
+        board
+    }
+}
+
+#[test]
+fn jobs_run_one_after_the_other_in_order() {
+    let mut session = Session::new();
+    let board = session.attach();
+
+    let ids = [
+        session.worker.send(Command::Apply(settings(), false)),
+        session.worker.send(Command::Send("mot M1 10".to_string())),
+        session.worker.send(Command::Read(Settings::default())),
+        session.worker.send(Command::Disconnect),
+    ];
+    for id in ids {
+        assert_eq!(session.wait(id, "queued job"), JobStatus::Succeeded);
+    }
+    let attach = session.started()[0];
+    assert_eq!(session.started(), [attach, ids[0], ids[1], ids[2], ids[3]]);
+
+    // each job is finished before the next starts
+    let finished = |session: &Session, id| session.events.iter().position(|x| matches!(x, Event::Job(job) if job.id == id && job.status.is_finished())).unwrap();
+    let running = |session: &Session, id| session.events.iter().position(|x| matches!(x, Event::Job(job) if job.id == id && job.status == JobStatus::Running)).unwrap();
+    for pair in ids.windows(2) {
+        assert!(finished(&session, pair[0]) < running(&session, pair[1]));
+    }
+    assert_eq!(board.flash.lock().unwrap().hostname, "robot1");
+}
+
+#[test]
+fn cancel_a_queued_job() {
+    let mut session = Session::new();
+    let board = session.attach();
+
+    let apply = session.worker.send(Command::Apply(settings(), false));
+    let send = session.worker.send(Command::Send("mot M1 10".to_string()));
+    session.worker.cancel(send);
+    assert_eq!(session.wait(apply, "apply"), JobStatus::Succeeded);
+    assert_eq!(session.wait(send, "send"), JobStatus::Cancelled);
+
+    // it never ran, so the motor didn't move
+    assert!(session.job_events(send).iter().all(|x| x.status != JobStatus::Running));
+    assert_eq!(board.io.lock().unwrap().outputs.get("M1"), Some(&0));
+}
+
+#[test]
+fn cancel_a_running_job() {
+    let mut session = Session::new();
+    let board = session.attach();
+
+    let apply = session.worker.send(Command::Apply(settings(), true));
+    let deadline = Instant::now() + JOB_TIMEOUT;
+    while !session.job_events(apply).iter().any(|x| x.progress.is_some()) {
+        session.poll(deadline, "apply to make progress");
+    }
+    session.worker.cancel(apply);
+    assert_eq!(session.wait(apply, "apply"), JobStatus::Cancelled);
+
+    // the board is back at the sketch and nothing was saved
+    assert_eq!(board.flash.lock().unwrap().hostname, "");
+    assert_eq!(session.run(Command::Send("mot M1 10".to_string())), JobStatus::Succeeded);
+    session.wait_line("suc mot 10");
+}
+
+#[test]
+fn progress_grows_to_one() {
+    let mut session = Session::new();
+    session.attach();
+
+    let apply = session.worker.send(Command::Apply(settings(), true));
+    assert_eq!(session.wait(apply, "apply"), JobStatus::Succeeded);
+    let progress: Vec<f32> = session.job_events(apply).iter().filter_map(|x| x.progress).collect();
+    assert!(progress.len() > 2, "{:?}", progress);
+    assert!(progress.windows(2).all(|x| x[0] < x[1]), "{:?}", progress);
+    assert!(progress[0] > 0.0, "{:?}", progress);
+    assert_eq!(progress.last(), Some(&1.0));
+}
@@ -0,0 +1,125 @@
+// Runs the worker and the menus against the simulator and the other
+// transports, the way the frontends use them
+mod apply;
+mod connection;
+mod jobs;
+mod network;
+#[cfg(unix)]
+mod pty;
+mod recover;
+mod replay;
+#[cfg(unix)]
+mod sysfs;
+
+use std::thread;
+use std::time::{Duration, Instant};
+use super::simulator::Simulator;
+use super::worker::JobId;
+use super::{Check, Command, Direction, Event, JobStatus, Settings, Snapshot, Type, Worker, WorkerOptions};
+
+const JOB_TIMEOUT: Duration = Duration::from_secs(60);
+
+// A board with a short boot, most tests reboot it a few times
+fn simulator() -> Simulator {
+    let mut simulator = Simulator::new(100);
+    simulator.boot_delay = Duration::from_millis(10);
+    simulator
+}
+
+// Differs from the factory settings in every menu
+fn settings() -> Settings {
+    Settings {
+        ssid: "workshop".to_string(),
+        password: "secret42".to_string(),
+        rgb_led_num: 3,
+        create_swarm: true,
+        swarm_name: "factory".to_string(),
+        swarm_pin: "42".to_string(),
+        hostname: "robot1".to_string(),
+        swarm_type: Type::JST,
+        input_ports: vec!["start".to_string(), "stop".to_string(), String::new(), String::new()],
+        output_ports: vec!["belt".to_string(), String::new()],
+        led_ports: vec!["lamp".to_string(), String::new(), String::new()],
+        servo_port: "arm".to_string(),
+    }
+}
+
+fn json(settings: &Settings) -> serde_json::Value {
+    serde_json::to_value(settings).unwrap()
+}
+
+// A worker and everything it reported
+struct Session {
+    worker: Worker,
+    events: Vec<Event>,
+}
+
+impl Session {
+    // Ports are only probed on request, so tests don't touch real ones
+    fn new() -> Session {
+        let options = WorkerOptions { manual_probe: true, ..WorkerOptions::default() };
+        Session { worker: Worker::spawn(simulator(), options), events: vec![] }
+    }
+
+    fn poll(&mut self, deadline: Instant, what: &str) {
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        let events = self.worker.poll();
+        if events.is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.events.extend(events);
+    }
+
+    // Sends `command` and waits until its job is finished
+    fn run(&mut self, command: Command) -> JobStatus {
+        let label = command.label();
+        let id = self.worker.send(command);
+        self.wait(id, &label)
+    }
+
+    // Waits until job `id` is finished
+    fn wait(&mut self, id: JobId, label: &str) -> JobStatus {
+        let deadline = Instant::now() + JOB_TIMEOUT;
+        let mut seen = 0;
+        loop {
+            for event in &self.events[seen..] {
+                if let Event::Job(job) = event {
+                    if job.id == id && job.status.is_finished() {
+                        return job.status.clone();
+                    }
+                }
+            }
+            seen = self.events.len();
+            self.poll(deadline, label);
+        }
+    }
+
+    // Waits for a line the board prints, e.g. ">>>" once it booted
+    fn wait_line(&mut self, text: &str) {
+        let deadline = Instant::now() + JOB_TIMEOUT;
+        while !self.events.iter().any(|x| matches!(x, Event::Line(line) if line.text == text)) {
+            self.poll(deadline, text);
+        }
+    }
+
+    fn checks(&self) -> Vec<Check> {
+        self.events.iter().rev().find_map(|x| match x {
+            Event::Verification(checks) if !checks.is_empty() => Some(checks.clone()),
+            _ => None,
+        }).unwrap_or_default()
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        self.events.iter().rev().find_map(|x| match x {
+            Event::State(snapshot) => Some(snapshot.clone()),
+            _ => None,
+        }).unwrap_or_default()
+    }
+
+    fn settings(&self) -> Option<Settings> {
+        self.events.iter().rev().find_map(|x| match x {
+            Event::Settings(settings) => Some(settings.clone()),
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,134 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use super::*;
+use crate::serial::network::NetworkTransport;
+use crate::serial::probe::Detected;
+use crate::serial::transport::Transport;
+use crate::serial::{Profile, Signal};
+
+// The bridge's side of a connection, for the simulator to run on
+struct Socket(TcpStream);
+
+impl Transport for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Err(io::ErrorKind::TimedOut.into()),
+            result => result,
+        }
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.0.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))
+    }
+}
+
+#[test]
+fn apply_over_a_raw_bridge() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = format!("tcp://{}", listener.local_addr().unwrap());
+    let board = simulator();
+    let bridge = board.clone();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        bridge.run(Socket(stream));
+    });
+
+    let mut session = Session::new();
+    assert_eq!(session.run(Command::Connect(port, Profile::default())), JobStatus::Succeeded);
+    session.wait_line(">>>");
+
+    let target = settings();
+    assert_eq!(session.run(Command::Apply(target.clone(), false)), JobStatus::Succeeded);
+    assert_eq!(board.flash.lock().unwrap().hostname, "robot1");
+    assert_eq!(session.run(Command::Read(target.clone())), JobStatus::Succeeded);
+    assert_eq!(json(&session.settings().unwrap()), json(&target));
+}
+
+#[test]
+fn probe_finds_the_sketch_behind_a_bridge() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = format!("tcp://{}", listener.local_addr().unwrap());
+    let board = simulator();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        board.run(Socket(stream));
+    });
+
+    let mut session = Session::new();
+    session.worker.set_bridges(vec![port.clone()]);
+    assert_eq!(session.run(Command::Probe), JobStatus::Succeeded);
+    let probes = session.snapshot().probes;
+    assert!(matches!(probes.get(&port), Some(Detected::Sketch { host: Some(host), .. }) if host == "ftSwarm100"), "{:?}", probes);
+    // local ports without a USB adapter are left alone
+    assert_eq!(probes.len(), 1, "{:?}", probes);
+}
+
+// Reads until `end` shows up, telnet commands alone don't return anything
+fn read_until(transport: &mut NetworkTransport, end: u8) -> Vec<u8> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut data = vec![];
+    while data.last() != Some(&end) {
+        assert!(Instant::now() < deadline, "got {:?}", data);
+        let mut buf = [0; 64];
+        match transport.read(&mut buf) {
+            Ok(n) => data.extend(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => panic!("{}", e),
+        }
+    }
+    data
+}
+
+#[test]
+fn rfc2217_negotiation_and_escaping() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = format!("rfc2217://{}", listener.local_addr().unwrap());
+    let profile = Profile { dtr: Signal::Set, rts: Signal::Cleared, ..Profile::default() };
+    let mut transport = NetworkTransport::open(&port, &profile).unwrap();
+    let (mut bridge, _) = listener.accept().unwrap();
+
+    let negotiation = [
+        255, 251, 0, 255, 253, 0, 255, 253, 3, 255, 251, 44,
+        // 115200 baud, 8 data bits, no parity, 1 stop bit, no flow control
+        255, 250, 44, 1, 0, 1, 194, 0, 255, 240,
+        255, 250, 44, 2, 8, 255, 240,
+        255, 250, 44, 3, 1, 255, 240,
+        255, 250, 44, 4, 1, 255, 240,
+        255, 250, 44, 5, 1, 255, 240,
+        // DTR on, RTS off
+        255, 250, 44, 5, 8, 255, 240,
+        255, 250, 44, 5, 12, 255, 240,
+    ];
+    let mut received = vec![0; negotiation.len()];
+    bridge.read_exact(&mut received).unwrap();
+    assert_eq!(received, negotiation);
+
+    // DO ECHO is refused, WILL SUPPRESS-GO-AHEAD and the com port answer
+    // are taken silently, an escaped 255 is data
+    bridge.write_all(&[255, 253, 1, 255, 251, 3, 255, 250, 44, 101, 0, 1, 194, 0, 255, 240]).unwrap();
+    bridge.write_all(&[b'a', b'b', 255, 255, b'c', b'\n']).unwrap();
+    assert_eq!(read_until(&mut transport, b'\n'), [b'a', b'b', 255, b'c', b'\n']);
+    let mut reply = [0; 3];
+    bridge.read_exact(&mut reply).unwrap();
+    assert_eq!(reply, [255, 252, 1]);
+
+    // a command split across reads
+    bridge.write_all(&[255, 250, 44]).unwrap();
+    thread::sleep(Duration::from_millis(50));
+    assert!(matches!(transport.read(&mut [0; 64]), Err(e) if e.kind() == io::ErrorKind::TimedOut));
+    bridge.write_all(&[104, 1, 255, 240, b'd', b'\n']).unwrap();
+    assert_eq!(read_until(&mut transport, b'\n'), b"d\n");
+
+    transport.write_all(&[b'x', 255, b'\n']).unwrap();
+    let mut sent = [0; 4];
+    bridge.read_exact(&mut sent).unwrap();
+    assert_eq!(sent, [b'x', 255, 255, b'\n']);
+}
@@ -0,0 +1,25 @@
+use super::*;
+use crate::serial::transport::PtyTransport;
+use crate::serial::Profile;
+
+// The board sits behind a pseudo terminal, the worker opens its tty like a
+// USB adapter's
+#[test]
+fn apply_over_a_serial_port() {
+    let pty = PtyTransport::open().unwrap();
+    let port = pty.slave_path().to_string_lossy().into_owned();
+    let board = simulator();
+    board.run(pty);
+
+    let mut session = Session::new();
+    assert_eq!(session.run(Command::Connect(port.clone(), Profile::default())), JobStatus::Succeeded);
+    // the board booted before the port was opened
+    assert_eq!(session.run(Command::Send("res".to_string())), JobStatus::Succeeded);
+    session.wait_line(">>>");
+
+    let target = settings();
+    assert_eq!(session.run(Command::Apply(target.clone(), false)), JobStatus::Succeeded);
+    assert_eq!(board.flash.lock().unwrap().hostname, "robot1");
+    assert_eq!(session.run(Command::Read(target.clone())), JobStatus::Succeeded);
+    assert_eq!(json(&session.settings().unwrap()), json(&target));
+}
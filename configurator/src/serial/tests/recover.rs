@@ -0,0 +1,50 @@
+use super::*;
+use crate::serial::expect::{Expect, Output, BOOT_TIMEOUT, PROMPT_TIMEOUT};
+use crate::serial::menu;
+use crate::serial::simulator::Nvs;
+
+// Left at the SSID prompt, as after a timeout: the SSID escape clears is
+// never saved
+#[test]
+fn recover_from_a_prompt() {
+    let simulator = simulator();
+    let mut host = simulator.connect();
+    let mut lines = vec![];
+    let mut output = |output: Output| {
+        if let Output::Progress(message) = output {
+            lines.push(message.to_string());
+        }
+    };
+    let mut expect = Expect::new(&mut host, &mut output);
+    expect.expect(">>>", BOOT_TIMEOUT).unwrap();
+
+    expect.step("set SSID");
+    expect.send_expect("stp", "main>", PROMPT_TIMEOUT).unwrap();
+    expect.send_expect("1", "wifi>", PROMPT_TIMEOUT).unwrap();
+    expect.send_expect("2", "Please enter new SSID: ", PROMPT_TIMEOUT).unwrap();
+
+    menu::recover(&mut expect).unwrap();
+    assert_eq!(simulator.flash.lock().unwrap().ssid, Nvs::factory(100).ssid);
+    expect.send_expect("mot M1 10", "suc mot 10", PROMPT_TIMEOUT).unwrap();
+    drop(expect);
+    assert_eq!(lines, ["board is back at the sketch prompt after \"set SSID\"", "resetting to drop unsaved changes"]);
+}
+
+#[test]
+fn recover_leaves_a_running_sketch_alone() {
+    let simulator = simulator();
+    let mut host = simulator.connect();
+    let mut resets = 0;
+    let mut output = |output: Output| {
+        if let Output::Received(data) = output {
+            resets += String::from_utf8_lossy(data).matches(">>>").count();
+        }
+    };
+    let mut expect = Expect::new(&mut host, &mut output);
+    expect.expect(">>>", BOOT_TIMEOUT).unwrap();
+
+    menu::recover(&mut expect).unwrap();
+    expect.send_expect("mot M1 10", "suc mot 10", PROMPT_TIMEOUT).unwrap();
+    drop(expect);
+    assert_eq!(resets, 1);
+}
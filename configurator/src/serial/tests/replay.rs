@@ -0,0 +1,40 @@
+use std::fs;
+use super::*;
+use crate::serial::simulator::DEMO_PORT;
+use crate::serial::{Profile, Recording, Replay};
+
+// Records an apply to the simulator, then applies again against the
+// recording instead of a board
+#[test]
+fn apply_against_a_recorded_session() {
+    let path = std::env::temp_dir().join(format!("swarm-session-{}.jsonl", std::process::id()));
+    let target = settings();
+
+    let mut session = Session::new();
+    session.worker.start_recording(&path, DEMO_PORT).unwrap();
+    assert_eq!(session.run(Command::Connect(DEMO_PORT.to_string(), Profile::default())), JobStatus::Succeeded);
+    session.wait_line(">>>");
+    assert_eq!(session.run(Command::Apply(target.clone(), false)), JobStatus::Succeeded);
+    session.worker.stop_recording();
+
+    let recording = Recording::load(&path).unwrap();
+    assert_eq!(recording.port, DEMO_PORT);
+    assert!(recording.chunks.iter().any(|x| x.direction == Direction::Tx));
+
+    let mut replayed = Session::new();
+    replayed.run(Command::Attach("replay".to_string(), Box::new(Replay::new(recording))));
+    replayed.wait_line(">>>");
+    assert_eq!(replayed.run(Command::Apply(target.clone(), false)), JobStatus::Succeeded);
+    let checks = replayed.checks();
+    assert!(!checks.is_empty() && checks.iter().all(|x| x.passed()));
+
+    // anything else than what was recorded is refused
+    let mut replayed = Session::new();
+    replayed.run(Command::Attach("replay".to_string(), Box::new(Replay::new(Recording::load(&path).unwrap()))));
+    replayed.wait_line(">>>");
+    let other = Settings { hostname: "robot2".to_string(), ..target };
+    let JobStatus::Failed(error) = replayed.run(Command::Apply(other, false)) else { panic!("apply succeeded") };
+    assert_eq!(error, "step \"set alias hostname\" failed: expected '1', got '2'");
+
+    fs::remove_file(&path).unwrap();
+}
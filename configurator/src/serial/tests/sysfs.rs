@@ -0,0 +1,147 @@
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use super::*;
+use crate::serial::transport::PtyTransport;
+use crate::serial::usb::{self, UsbDevice, SYSFS_ROOT_VARIABLE};
+use crate::serial::{ConnectOptions, Profile};
+
+// The root is shared by the process, tests setting it take turns
+static SYSFS: Mutex<()> = Mutex::new(());
+
+fn fake_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("swarm-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    root
+}
+
+fn device(dir: &Path, files: &[(&str, &str)]) {
+    fs::create_dir_all(dir).unwrap();
+    for (name, text) in files {
+        fs::write(dir.join(name), format!("{}\n", text)).unwrap();
+    }
+}
+
+fn tty(root: &Path, name: &str, device: &Path) {
+    let dir = root.join("class/tty").join(name);
+    fs::create_dir_all(&dir).unwrap();
+    symlink(device, dir.join("device")).unwrap();
+}
+
+// A tree laid out like the kernel's: an FTDI adapter with a serial number,
+// a CDC ACM board without one and an on-board UART
+#[test]
+fn adapters_from_a_fake_sysfs() {
+    let _sysfs = SYSFS.lock().unwrap();
+    let root = fake_root("sysfs");
+    let hub = root.join("devices/pci0000:00/0000:00:14.0/usb1/1-1");
+
+    let ftdi = hub.join("1-1.2");
+    device(&ftdi, &[("idVendor", "0403"), ("idProduct", "6001"), ("serial", "A50285BI"), ("manufacturer", "FTDI"), ("product", "FT232R USB UART")]);
+    // ttyUSB ports hang below the interface
+    let port = ftdi.join("1-1.2:1.0/ttyUSB0");
+    fs::create_dir_all(&port).unwrap();
+    tty(&root, "ttyUSB0", &port);
+
+    let acm = hub.join("1-1.3");
+    device(&acm, &[("idVendor", "303a"), ("idProduct", "1001"), ("serial", "")]);
+    let interface = acm.join("1-1.3:1.0");
+    fs::create_dir_all(&interface).unwrap();
+    tty(&root, "ttyACM0", &interface);
+
+    let uart = root.join("devices/platform/serial8250");
+    fs::create_dir_all(&uart).unwrap();
+    tty(&root, "ttyS0", &uart);
+
+    std::env::set_var(SYSFS_ROOT_VARIABLE, &root);
+    let sysfs = usb::sysfs_root();
+    assert_eq!(sysfs, root);
+
+    let ftdi = usb::lookup(&sysfs, "/dev/ttyUSB0").unwrap();
+    assert_eq!(
+        ftdi,
+        UsbDevice {
+            vendor_id: 0x0403,
+            product_id: 0x6001,
+            serial: Some("A50285BI".to_string()),
+            manufacturer: Some("FTDI".to_string()),
+            product: Some("FT232R USB UART".to_string()),
+            bus_path: "1-1.2".to_string(),
+        }
+    );
+    assert_eq!(ftdi.identity(), "0403:6001:A50285BI");
+    assert_eq!(ftdi.describe(), "0403:6001 FTDI FT232R USB UART (serial A50285BI)");
+
+    // an empty serial number counts as none, the USB port tells it apart
+    let acm = usb::lookup(&sysfs, "/dev/ttyACM0").unwrap();
+    assert_eq!(acm.serial, None);
+    assert_eq!(acm.identity(), "303a:1001@1-1.3");
+    assert_eq!(acm.describe(), "303a:1001");
+
+    assert_eq!(usb::lookup(&sysfs, "/dev/ttyS0"), None);
+    assert_eq!(usb::lookup(&sysfs, "/dev/ttyUSB1"), None);
+
+    std::env::remove_var(SYSFS_ROOT_VARIABLE);
+    fs::remove_dir_all(&root).unwrap();
+}
+
+// A board behind a pseudo terminal, listed as a USB adapter. The local ports
+// come from the system, so the pty is passed as an extra port.
+fn pty_board(root: &Path, adapter: &Path, board: &Simulator) -> String {
+    let pty = PtyTransport::open().unwrap();
+    let port = pty.slave_path().to_string_lossy().into_owned();
+    board.run(pty);
+    let name = Path::new(&port).file_name().unwrap().to_string_lossy().into_owned();
+    tty(root, &name, adapter);
+    port
+}
+
+// The adapter moves to another port while unplugged: the connection is lost,
+// found again by the USB identity and the startup commands are sent again
+#[test]
+fn reconnect_to_the_same_adapter() {
+    let _sysfs = SYSFS.lock().unwrap();
+    let root = fake_root("reconnect");
+    let adapter = root.join("devices/pci0000:00/0000:00:14.0/usb1/1-1/1-1.2");
+    device(&adapter, &[("idVendor", "0403"), ("idProduct", "6001"), ("serial", "A50285BI")]);
+    std::env::set_var(SYSFS_ROOT_VARIABLE, &root);
+    let board = simulator();
+    let first = pty_board(&root, &adapter, &board);
+
+    let options = WorkerOptions {
+        connect: ConnectOptions { reconnect: true, startup: vec!["mot M1 10".to_string()], rerun_startup: true },
+        bridges: vec![first.clone()],
+        manual_probe: true,
+    };
+    let mut session = Session { worker: Worker::spawn(simulator(), options), events: vec![] };
+    // the worker took the root when it started
+    std::env::remove_var(SYSFS_ROOT_VARIABLE);
+    assert_eq!(session.run(Command::Connect(first.clone(), Profile::default())), JobStatus::Succeeded);
+    session.wait_line("suc mot 10");
+    board.io.lock().unwrap().outputs.insert("M1".to_string(), 0);
+
+    // unplugged
+    fs::remove_dir_all(root.join("class/tty")).unwrap();
+    let deadline = Instant::now() + JOB_TIMEOUT;
+    while session.snapshot().lost.is_none() {
+        session.poll(deadline, "the connection to be lost");
+    }
+    let snapshot = session.snapshot();
+    assert_eq!(snapshot.connected, None);
+    assert_eq!(snapshot.lost, Some(first.clone()));
+    assert_eq!(snapshot.lost_device.map(|x| x.identity()), Some("0403:6001:A50285BI".to_string()));
+    session.wait_line(&format!("lost connection to {}", first));
+
+    // and plugged in again, showing up as another tty
+    let second = pty_board(&root, &adapter, &board);
+    session.worker.set_bridges(vec![first.clone(), second.clone()]);
+    session.wait_line(&format!("reconnected to {}, was {}", second, first));
+    assert_eq!(session.snapshot().connected, Some(second));
+
+    let deadline = Instant::now() + JOB_TIMEOUT;
+    while board.io.lock().unwrap().outputs.get("M1") != Some(&10) {
+        session.poll(deadline, "the startup commands");
+    }
+    fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,267 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use serial2::SerialPort;
+use super::network::{self, NetworkTransport};
+use super::profile::Profile;
+
+// Same default as serial2 uses for its ports
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Byte stream to a board. Reads block for at most the configured timeout and
+// then fail with `io::ErrorKind::TimedOut`, like a serial2 port does.
+pub trait Transport: Send {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>;
+    fn flush(&mut self) -> io::Result<()>;
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()>;
+
+    fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => buf = &buf[n..],
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transport for SerialPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        SerialPort::read(self, buf)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        SerialPort::write(self, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        SerialPort::flush(self)
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.set_read_timeout(timeout)?;
+        self.set_write_timeout(timeout)
+    }
+}
+
+// Opens a local serial port (or the slave side of a pseudo terminal), or a
+// port of a network bridge
+pub fn open(path: &str, profile: &Profile) -> io::Result<Box<dyn Transport>> {
+    if network::is_network(path) {
+        return Ok(Box::new(NetworkTransport::open(path, profile)?));
+    }
+    Ok(Box::new(profile.open(path)?))
+}
+
+// Ports in use by this process. serial2 doesn't lock a port, so two
+// sessions, or a session and the port probe, could otherwise share one.
+static CLAIMED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+// Reserves a port until it is dropped
+pub struct Claim(String);
+
+impl Claim {
+    // None if the port is already claimed
+    pub fn take(path: &str) -> Option<Claim> {
+        CLAIMED.lock().unwrap().insert(path.to_string()).then(|| Claim(path.to_string()))
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        CLAIMED.lock().unwrap().remove(&self.0);
+    }
+}
+
+#[derive(Default)]
+struct Pipe {
+    data: Mutex<(VecDeque<u8>, bool)>,
+    ready: Condvar,
+}
+
+impl Pipe {
+    fn close(&self) {
+        self.data.lock().unwrap().1 = true;
+        self.ready.notify_all();
+    }
+}
+
+// One end of an in-memory byte pipe. Whatever is written to one end of a
+// pair can be read from the other one. Dropping an end closes the pipe:
+// the peer reads the remaining bytes and then gets `Ok(0)`.
+pub struct MemoryTransport {
+    rx: Arc<Pipe>,
+    tx: Arc<Pipe>,
+    timeout: Duration,
+}
+
+impl MemoryTransport {
+    pub fn pair() -> (MemoryTransport, MemoryTransport) {
+        let a = Arc::new(Pipe::default());
+        let b = Arc::new(Pipe::default());
+
+        (
+            MemoryTransport { rx: a.clone(), tx: b.clone(), timeout: DEFAULT_TIMEOUT },
+            MemoryTransport { rx: b, tx: a, timeout: DEFAULT_TIMEOUT },
+        )
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = Instant::now() + self.timeout;
+        let mut data = self.rx.data.lock().unwrap();
+
+        while data.0.is_empty() {
+            if data.1 {
+                return Ok(0);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            data = self.rx.ready.wait_timeout(data, deadline - now).unwrap().0;
+        }
+
+        let n = buf.len().min(data.0.len());
+        for (dst, src) in buf.iter_mut().zip(data.0.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut data = self.tx.data.lock().unwrap();
+        if data.1 {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        data.0.extend(buf);
+        self.tx.ready.notify_all();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.rx.close();
+        self.tx.close();
+    }
+}
+
+#[cfg(unix)]
+pub use pty::PtyTransport;
+
+#[cfg(unix)]
+mod pty {
+    use std::ffi::CStr;
+    use std::fs::File;
+    use std::io::{self, Read, Write};
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+    use super::{Transport, DEFAULT_TIMEOUT};
+
+    // Master side of a pseudo terminal. The slave side shows up as a tty at
+    // `slave_path()` and can be opened like any other serial port, so code
+    // running against a real `SerialPort` can be pointed at it.
+    pub struct PtyTransport {
+        master: File,
+        // kept open so reads on the master don't fail while nobody else has
+        // the slave opened
+        _slave: File,
+        slave_path: PathBuf,
+        timeout: Duration,
+    }
+
+    fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result)
+        }
+    }
+
+    impl PtyTransport {
+        pub fn open() -> io::Result<PtyTransport> {
+            unsafe {
+                let fd = check(libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY))?;
+                let master = File::from_raw_fd(fd);
+                check(libc::grantpt(fd))?;
+                check(libc::unlockpt(fd))?;
+
+                let mut name = [0 as libc::c_char; 128];
+                if libc::ptsname_r(fd, name.as_mut_ptr(), name.len()) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let slave_path = PathBuf::from(CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned());
+
+                let slave = std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&slave_path)?;
+
+                // raw mode: no echo and no line ending translation
+                let mut termios = std::mem::zeroed::<libc::termios>();
+                check(libc::tcgetattr(slave.as_raw_fd(), &mut termios))?;
+                libc::cfmakeraw(&mut termios);
+                check(libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios))?;
+
+                Ok(PtyTransport {
+                    master,
+                    _slave: slave,
+                    slave_path,
+                    timeout: DEFAULT_TIMEOUT,
+                })
+            }
+        }
+
+        pub fn slave_path(&self) -> &Path {
+            &self.slave_path
+        }
+    }
+
+    impl Transport for PtyTransport {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut poll = libc::pollfd {
+                fd: self.master.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout = self.timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+
+            if check(unsafe { libc::poll(&mut poll, 1, timeout) })? == 0 {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.master.read(buf)
+        }
+
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.master.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.master.flush()
+        }
+
+        fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+            self.timeout = timeout;
+            Ok(())
+        }
+    }
+}
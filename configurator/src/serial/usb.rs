@@ -0,0 +1,80 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Serialize;
+
+// Where sysfs is mounted, can be pointed at a fake tree
+pub const SYSFS_ROOT: &str = "/sys";
+pub const SYSFS_ROOT_VARIABLE: &str = "SWARM_SYSFS_ROOT";
+
+// USB device behind a serial port
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UsbDevice {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub serial: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    // position on the bus, e.g. "1-1.2"
+    pub bus_path: String,
+}
+
+impl UsbDevice {
+    // Stays the same across replugs and reboots. Adapters without a serial
+    // number are told apart by the USB port they are plugged into.
+    pub fn identity(&self) -> String {
+        match &self.serial {
+            Some(serial) => format!("{:04x}:{:04x}:{}", self.vendor_id, self.product_id, serial),
+            None => format!("{:04x}:{:04x}@{}", self.vendor_id, self.product_id, self.bus_path),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let mut text = format!("{:04x}:{:04x}", self.vendor_id, self.product_id);
+        for part in [&self.manufacturer, &self.product].into_iter().flatten() {
+            text.push(' ');
+            text.push_str(part);
+        }
+        if let Some(serial) = &self.serial {
+            text.push_str(&format!(" (serial {})", serial));
+        }
+        text
+    }
+}
+
+// The configured sysfs root
+pub fn sysfs_root() -> PathBuf {
+    std::env::var_os(SYSFS_ROOT_VARIABLE).map(PathBuf::from).unwrap_or_else(|| PathBuf::from(SYSFS_ROOT))
+}
+
+// Looks up the USB device of a port like "/dev/ttyUSB0". None for ports
+// that aren't USB, e.g. on-board UARTs, or without sysfs.
+pub fn lookup(root: &Path, port: &str) -> Option<UsbDevice> {
+    let name = Path::new(port).file_name()?;
+    let root = fs::canonicalize(root).ok()?;
+    // a link into the devices tree, the interface for ttyACM and the
+    // usb-serial port below it for ttyUSB
+    let mut dir = fs::canonicalize(root.join("class/tty").join(name).join("device")).ok()?;
+
+    while dir.starts_with(&root) {
+        if dir.join("idVendor").is_file() {
+            let read = |file: &str| {
+                fs::read_to_string(dir.join(file)).ok().map(|x| x.trim().to_string()).filter(|x| !x.is_empty())
+            };
+            let hex = |file: &str| read(file).and_then(|x| u16::from_str_radix(&x, 16).ok());
+
+            return Some(UsbDevice {
+                vendor_id: hex("idVendor")?,
+                product_id: hex("idProduct")?,
+                serial: read("serial"),
+                manufacturer: read("manufacturer"),
+                product: read("product"),
+                bus_path: dir.file_name()?.to_string_lossy().to_string(),
+            });
+        }
+
+        if !dir.pop() {
+            break;
+        }
+    }
+    None
+}
@@ -0,0 +1,814 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use serial2::SerialPort;
+use super::error::SerialError;
+use super::expect::{Expect, Output};
+use super::lines::{Direction, Line, LineAssembler};
+use super::menu::{self, Check};
+use super::network;
+use super::probe::{self, Detected};
+use super::profile::Profile;
+use super::protocol::SketchEvent;
+use super::raw::{self, Chunk, Tap};
+use super::recording::{Recorder, Recording};
+use super::simulator::{BoardIo, Simulator, DEMO_PORT};
+use super::transport::{self, Claim, Transport};
+use super::usb::{self, UsbDevice};
+use super::{Command, Settings};
+
+// How often the port list is refreshed
+const PORT_SCAN_INTERVAL: Duration = Duration::from_secs(1);
+// Startup commands wait for the sketch prompt or this long without output,
+// a key press while the board boots enters the bios settings
+const STARTUP_QUIET: Duration = Duration::from_millis(500);
+// The sketch takes a pause of 3ms as the end of a command
+const COMMAND_GAP: Duration = Duration::from_millis(50);
+
+// What the worker tells the UI
+pub enum Event {
+    // the worker's state changed
+    State(Snapshot),
+    // console output: lines received, sent and messages of the
+    // configurator itself
+    Line(Line),
+    // a subscribed input changed
+    Value { name: String, value: i32 },
+    // configuration read from the board
+    Settings(Settings),
+    // result of the last apply
+    Verification(Vec<Check>),
+    // a job was queued or its status or progress changed
+    Job(Job),
+    // traffic as it went over the line
+    Bytes(Chunk),
+}
+
+pub type JobId = u64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed(String),
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, JobStatus::Queued | JobStatus::Running)
+    }
+}
+
+impl fmt::Display for JobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobStatus::Queued => write!(f, "queued"),
+            JobStatus::Running => write!(f, "running"),
+            JobStatus::Succeeded => write!(f, "succeeded"),
+            JobStatus::Failed(reason) => write!(f, "failed: {}", reason),
+            JobStatus::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+// A command on its way through the worker
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: JobId,
+    pub label: String,
+    pub status: JobStatus,
+    // share done of long running jobs, 0 to 1
+    pub progress: Option<f32>,
+}
+
+#[derive(Clone, Default)]
+pub struct Snapshot {
+    pub ports: Vec<String>,
+    // what was found on the ports, see probe::probe()
+    pub probes: BTreeMap<String, Detected>,
+    // USB adapters behind the ports
+    pub devices: BTreeMap<String, UsbDevice>,
+    // port of the open connection
+    pub connected: Option<String>,
+    // a menu operation is running and can be cancelled
+    pub busy: bool,
+    pub last_error: Option<String>,
+    // port of a connection that broke, reconnected if the options allow
+    pub lost: Option<String>,
+    // its USB adapter, which is looked for on any port
+    pub lost_device: Option<UsbDevice>,
+    pub demo_board: Option<Arc<Mutex<BoardIo>>>,
+}
+
+// How a session treats its connection
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    // reconnect once a lost port is back
+    pub reconnect: bool,
+    // lines sent after connecting, e.g. subscriptions
+    pub startup: Vec<String>,
+    // send them again after a reconnect or when the board restarted
+    pub rerun_startup: bool,
+}
+
+// What a worker starts with, set before its thread runs so the first port
+// scan already follows it
+#[derive(Debug, Clone, Default)]
+pub struct WorkerOptions {
+    pub connect: ConnectOptions,
+    // ports of network bridges, listed with the local ones
+    pub bridges: Vec<String>,
+    // ports are only probed on Command::Probe, for scripts that open a
+    // port right away and would be held up by probing
+    pub manual_probe: bool,
+}
+
+// Handle to the thread that owns the serial port. Commands are queued as
+// jobs and run in the order they were sent.
+pub struct Worker {
+    commands: Sender<(JobId, Command)>,
+    events: Receiver<Event>,
+    jobs: Jobs,
+    next_id: JobId,
+    // events of this side, e.g. queued jobs
+    local: Vec<Event>,
+    // board behind the demo port, kept to restart the worker
+    simulator: Simulator,
+}
+
+// Shared between the handle and the thread, to cancel jobs and to change
+// settings that shouldn't wait in the queue
+#[derive(Clone, Default)]
+struct Jobs {
+    // job being run, 0 for none
+    running: Arc<AtomicU64>,
+    // set to stop the running job
+    cancel: Arc<AtomicBool>,
+    // queued jobs to skip
+    cancelled: Arc<Mutex<HashSet<JobId>>>,
+    options: Arc<Mutex<ConnectOptions>>,
+    // session file the traffic goes to
+    recorder: Arc<Mutex<Option<Recorder>>>,
+    // ports of network bridges, listed with the local ones
+    bridges: Arc<Mutex<Vec<String>>>,
+    // ports are only probed on Command::Probe
+    manual_probe: Arc<AtomicBool>,
+}
+
+impl Worker {
+    pub fn spawn(simulator: Simulator, options: WorkerOptions) -> Worker {
+        let (commands, command_rx) = mpsc::channel();
+        let (event_tx, events) = mpsc::channel();
+        let jobs = Jobs {
+            options: Arc::new(Mutex::new(options.connect)),
+            bridges: Arc::new(Mutex::new(options.bridges)),
+            manual_probe: Arc::new(AtomicBool::new(options.manual_probe)),
+            ..Jobs::default()
+        };
+
+        let thread = WorkerThread {
+            commands: command_rx,
+            events: event_tx,
+            jobs: jobs.clone(),
+            job: None,
+            serial: None,
+            claim: None,
+            device: None,
+            profile: Profile::default(),
+            sysfs: usb::sysfs_root(),
+            snapshot: Snapshot::default(),
+            simulator: simulator.clone(),
+            lines: LineAssembler::default(),
+            startup_pending: None,
+        };
+        thread::spawn(move || thread.run());
+
+        Worker { commands, events, jobs, next_id: 1, local: vec![], simulator }
+    }
+
+    // Queues a command
+    pub fn send(&mut self, command: Command) -> JobId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.local.push(Event::Job(Job { id, label: command.label(), status: JobStatus::Queued, progress: None }));
+        // a dead worker is noticed and replaced by poll()
+        let _ = self.commands.send((id, command));
+        id
+    }
+
+    // Takes effect for the next connect, reconnect or board restart
+    pub fn set_options(&self, options: ConnectOptions) {
+        *self.jobs.options.lock().unwrap() = options;
+    }
+
+    // Bridge ports like "tcp://host:port" or "rfc2217://host:port"
+    pub fn set_bridges(&self, bridges: Vec<String>) {
+        *self.jobs.bridges.lock().unwrap() = bridges;
+    }
+
+    // Records the traffic from now on, replacing a running recording
+    pub fn start_recording(&self, path: &Path, port: &str) -> std::io::Result<()> {
+        *self.jobs.recorder.lock().unwrap() = Some(Recorder::create(path, port)?);
+        Ok(())
+    }
+
+    pub fn stop_recording(&self) {
+        *self.jobs.recorder.lock().unwrap() = None;
+    }
+
+    // Skips a queued job or stops the running one
+    pub fn cancel(&self, id: JobId) {
+        self.jobs.cancelled.lock().unwrap().insert(id);
+        if self.jobs.running.load(Ordering::SeqCst) == id {
+            self.jobs.cancel.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // Events since the last call. A worker that panicked is restarted.
+    pub fn poll(&mut self) -> Vec<Event> {
+        let mut events = std::mem::take(&mut self.local);
+        loop {
+            match self.events.try_recv() {
+                Ok(event) => events.push(event),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    let next_id = self.next_id;
+                    let options = WorkerOptions {
+                        connect: self.jobs.options.lock().unwrap().clone(),
+                        bridges: self.jobs.bridges.lock().unwrap().clone(),
+                        manual_probe: self.jobs.manual_probe.load(Ordering::SeqCst),
+                    };
+                    *self = Worker::spawn(self.simulator.clone(), options);
+                    self.next_id = next_id;
+                    events.push(Event::Line(Line::new(Direction::Local, "serial worker crashed, restarting".to_string())));
+                    events.push(Event::State(Snapshot::default()));
+                    break;
+                }
+            }
+        }
+        events
+    }
+}
+
+// A dropped handle stops the running job, the thread exits once it is done
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.jobs.cancel.store(true, Ordering::SeqCst);
+    }
+}
+
+// The local ports, the bridges and the demo port, and the USB adapters
+// behind the local ones
+pub fn list_ports(bridges: &[String]) -> (Vec<String>, BTreeMap<String, UsbDevice>) {
+    enumerate_ports(&usb::sysfs_root(), bridges)
+}
+
+fn enumerate_ports(sysfs: &Path, bridges: &[String]) -> (Vec<String>, BTreeMap<String, UsbDevice>) {
+    let mut ports: Vec<String> = SerialPort::available_ports()
+        .map(|paths| paths.iter().map(|x| x.to_string_lossy().to_string()).collect())
+        .unwrap_or_default();
+    ports.extend(bridges.iter().cloned());
+    ports.push(DEMO_PORT.to_string());
+
+    let devices = ports.iter()
+        .filter_map(|port| Some((port.clone(), usb::lookup(sysfs, port)?)))
+        .collect();
+    (ports, devices)
+}
+
+struct WorkerThread {
+    commands: Receiver<(JobId, Command)>,
+    events: Sender<Event>,
+    jobs: Jobs,
+    // job being run
+    job: Option<Job>,
+    serial: Option<Box<dyn Transport>>,
+    // keeps other sessions off the connected port
+    claim: Option<Claim>,
+    // USB adapter of the connected port
+    device: Option<UsbDevice>,
+    // of the last connect, also used to reconnect
+    profile: Profile,
+    sysfs: PathBuf,
+    snapshot: Snapshot,
+    simulator: Simulator,
+    lines: LineAssembler,
+    // last output while the startup commands are waiting to be sent
+    startup_pending: Option<Instant>,
+}
+
+impl WorkerThread {
+    fn run(mut self) {
+        let mut last_scan: Option<Instant> = None;
+
+        loop {
+            if last_scan.is_none_or(|x| x.elapsed() >= PORT_SCAN_INTERVAL) {
+                self.scan_ports();
+                last_scan = Some(Instant::now());
+            }
+
+            // while connected the board is polled, reads block for the
+            // transport's timeout
+            let command = if self.serial.is_some() {
+                match self.commands.try_recv() {
+                    Ok(command) => Some(command),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            } else {
+                match self.commands.recv_timeout(PORT_SCAN_INTERVAL) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            };
+
+            if let Some((id, command)) = command {
+                self.run_job(id, command);
+            }
+
+            if self.serial.is_some() {
+                self.read_board();
+            }
+
+            if self.startup_pending.is_some_and(|x| x.elapsed() >= STARTUP_QUIET) {
+                self.run_startup();
+            }
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        let _ = self.events.send(event);
+    }
+
+    fn log(&self, message: String) {
+        self.emit(Event::Line(Line::new(Direction::Local, message)));
+    }
+
+    fn publish(&self) {
+        self.emit(Event::State(self.snapshot.clone()));
+    }
+
+    fn scan_ports(&mut self) {
+        let bridges = self.jobs.bridges.lock().unwrap().clone();
+        let (ports, devices) = enumerate_ports(&self.sysfs, &bridges);
+
+        if ports != self.snapshot.ports || devices != self.snapshot.devices {
+            // a different adapter on a known path is probed again
+            let old = std::mem::replace(&mut self.snapshot.devices, devices);
+            self.snapshot.probes.retain(|port, _| ports.contains(port) && old.get(port) == self.snapshot.devices.get(port));
+            self.snapshot.ports = ports;
+            self.publish();
+        }
+
+        self.check_connection();
+        let candidates = self.probe_candidates();
+        if !self.jobs.manual_probe.load(Ordering::SeqCst) {
+            probe::request(&candidates);
+        }
+        self.update_probes(&candidates);
+    }
+
+    // USB adapters and bridges. Other ports, e.g. on-board UARTs, aren't
+    // opened unless asked to, probing writes to a port that stays silent.
+    fn probe_candidates(&self) -> Vec<probe::Candidate> {
+        self.snapshot.ports.iter()
+            .filter_map(|port| match self.snapshot.devices.get(port) {
+                Some(device) => Some((port.clone(), device.identity())),
+                None => network::is_network(port).then(|| (port.clone(), port.clone())),
+            })
+            .collect()
+    }
+
+    // Takes over what the prober found
+    fn update_probes(&mut self, candidates: &[probe::Candidate]) {
+        let probes = probe::results(candidates);
+        if probes == self.snapshot.probes {
+            return;
+        }
+
+        for (port, detected) in probes.iter() {
+            if *detected != Detected::InUse && self.snapshot.probes.get(port) != Some(detected) {
+                self.log(format!("{} on {}", detected, port));
+            }
+        }
+        self.snapshot.probes = probes;
+        self.publish();
+    }
+
+    // A connection is lost when its port is gone or has another adapter
+    // now, and a lost one is reconnected once its adapter is back on any
+    // port, or its port is back for ports that aren't USB
+    fn check_connection(&mut self) {
+        if let Some(port) = &self.snapshot.connected {
+            // attached transports have no port in the list
+            // not every port shows up in the list, e.g. pseudo terminals,
+            // a bridge is only noticed to be gone when the connection breaks
+            let gone = !network::is_network(port)
+                && (!Path::new(port).exists() || self.snapshot.devices.get(port) != self.device.as_ref());
+            if self.claim.is_some() && gone {
+                self.lose();
+            }
+            return;
+        }
+
+        let Some(lost) = self.snapshot.lost.clone() else { return };
+        let options = self.jobs.options.lock().unwrap().clone();
+        let port = match &self.snapshot.lost_device {
+            Some(device) => self.snapshot.devices.iter()
+                .find(|(_, x)| x.identity() == device.identity())
+                .map(|(port, _)| port.clone()),
+            None => self.snapshot.ports.contains(&lost).then_some(lost.clone()),
+        };
+        let Some(port) = port.filter(|_| options.reconnect) else { return };
+
+        // the device may still be settling, it is tried again with the next scan
+        if self.connect(&port).is_ok() {
+            if port == lost {
+                self.log(format!("reconnected to {}", port));
+            } else {
+                self.log(format!("reconnected to {}, was {}", port, lost));
+            }
+            if !options.rerun_startup {
+                self.startup_pending = None;
+            }
+        }
+    }
+
+    fn connect(&mut self, port: &str) -> Result<(), SerialError> {
+        if port == DEMO_PORT {
+            self.snapshot.demo_board = Some(self.simulator.io.clone());
+            let serial = Box::new(self.simulator.connect());
+            self.set_connected(port.to_string(), serial);
+            return Ok(());
+        }
+
+        probe::wait(port);
+        let Some(claim) = Claim::take(port) else {
+            return Err(SerialError::Rejected { step: format!("open {}", port), reason: "in use by another session".to_string() });
+        };
+
+        let serial = transport::open(port, &self.profile).map_err(|error| SerialError::Io { step: format!("open {}", port), error })?;
+        self.claim = Some(claim);
+        self.device = self.snapshot.devices.get(port).cloned();
+        self.set_connected(port.to_string(), serial);
+        Ok(())
+    }
+
+    fn set_connected(&mut self, port: String, serial: Box<dyn Transport>) {
+        self.log(format!("connected to {}", port));
+        self.serial = Some(Box::new(Tap::new(serial, self.events.clone(), self.jobs.recorder.clone())));
+        self.lines.clear();
+        self.snapshot.connected = Some(port);
+        self.snapshot.lost = None;
+        self.snapshot.lost_device = None;
+        self.snapshot.last_error = None;
+        self.startup_pending = Some(Instant::now());
+        self.publish();
+    }
+
+    fn run_startup(&mut self) {
+        self.startup_pending = None;
+        let options = self.jobs.options.lock().unwrap().clone();
+        for (i, line) in options.startup.iter().filter(|x| !x.trim().is_empty()).enumerate() {
+            if i > 0 {
+                thread::sleep(COMMAND_GAP);
+            }
+            if self.send_line(line.clone()) != JobStatus::Succeeded {
+                break;
+            }
+        }
+    }
+
+    // The sketch lost its subscriptions
+    fn board_restarted(&mut self) {
+        let options = self.jobs.options.lock().unwrap().clone();
+        if self.startup_pending.is_some() {
+            self.run_startup();
+        } else if options.rerun_startup && !options.startup.is_empty() {
+            self.log("board restarted, sending the startup commands".to_string());
+            self.run_startup();
+        }
+    }
+
+    // The connection broke without the user asking for it
+    fn lose(&mut self) {
+        let port = self.snapshot.connected.clone().unwrap_or_default();
+        let device = self.device.clone();
+        self.log(format!("lost connection to {}", port));
+        self.disconnect();
+        self.snapshot.lost = Some(port);
+        self.snapshot.lost_device = device;
+        self.publish();
+    }
+
+    fn disconnect(&mut self) {
+        self.serial = None;
+        self.claim = None;
+        self.device = None;
+        self.startup_pending = None;
+        // the board may have changed while it was connected
+        if let Some(port) = &self.snapshot.connected {
+            probe::forget(port);
+            self.snapshot.probes.remove(port);
+        }
+        self.snapshot.connected = None;
+        self.snapshot.lost = None;
+        self.snapshot.lost_device = None;
+        self.snapshot.demo_board = None;
+        self.publish();
+    }
+
+    // Logs a failed command, and drops the connection if it is gone for good
+    fn report_error(&mut self, what: &str, error: SerialError) -> JobStatus {
+        self.log(format!("{} failed: {}", what, error));
+        self.snapshot.last_error = Some(format!("{} failed: {}", what, error));
+        if error.is_fatal() && self.snapshot.connected.is_some() {
+            self.lose();
+        } else {
+            self.publish();
+        }
+
+        match error {
+            SerialError::Cancelled { .. } => JobStatus::Cancelled,
+            error => JobStatus::Failed(error.to_string()),
+        }
+    }
+
+    fn succeeded(&mut self) -> JobStatus {
+        if self.snapshot.last_error.take().is_some() {
+            self.publish();
+        }
+        JobStatus::Succeeded
+    }
+
+    fn update_job(&mut self, status: JobStatus) {
+        if let Some(job) = &mut self.job {
+            job.status = status;
+            let job = job.clone();
+            self.emit(Event::Job(job));
+        }
+    }
+
+    fn run_job(&mut self, id: JobId, command: Command) {
+        let label = command.label();
+        self.job = Some(Job { id, label, status: JobStatus::Running, progress: None });
+
+        // see Worker::cancel()
+        self.jobs.running.store(id, Ordering::SeqCst);
+        self.jobs.cancel.store(false, Ordering::SeqCst);
+        let status = if self.jobs.cancelled.lock().unwrap().contains(&id) {
+            JobStatus::Cancelled
+        } else {
+            self.update_job(JobStatus::Running);
+            self.handle(command)
+        };
+
+        self.jobs.running.store(0, Ordering::SeqCst);
+        self.jobs.cancelled.lock().unwrap().remove(&id);
+        self.update_job(status);
+        self.job = None;
+    }
+
+    fn handle(&mut self, command: Command) -> JobStatus {
+        match command {
+            Command::Connect(port, profile) => {
+                if port.is_empty() {
+                    self.log("no port selected".to_string());
+                    return JobStatus::Failed("no port selected".to_string());
+                }
+
+                self.profile = profile;
+                if let Err(error) = self.connect(&port) {
+                    return self.report_error("connect", error);
+                }
+                JobStatus::Succeeded
+            }
+            Command::Attach(name, transport) => {
+                self.set_connected(name, transport);
+                JobStatus::Succeeded
+            }
+            Command::Disconnect => {
+                let port = self.snapshot.connected.clone().unwrap_or_default();
+                self.log(format!("disconnected from {}", port));
+                self.disconnect();
+                JobStatus::Succeeded
+            }
+            Command::Apply(settings, full) => {
+                self.emit(Event::Verification(vec![]));
+                match self.run_menus(|expect| menu::apply(expect, &settings, full)) {
+                    Ok(checks) => {
+                        let failed: Vec<_> = checks.iter().filter(|x| !x.passed()).map(|x| x.field.clone()).collect();
+                        self.emit(Event::Verification(checks));
+                        if failed.is_empty() {
+                            self.log("done!".to_string());
+                            self.succeeded()
+                        } else {
+                            let error = format!("board differs in {}", failed.join(", "));
+                            self.log(format!("apply failed: {}", error));
+                            self.snapshot.last_error = Some(format!("apply failed: {}", error));
+                            self.publish();
+                            JobStatus::Failed(error)
+                        }
+                    }
+                    Err(e) => self.report_error("apply", e),
+                }
+            }
+            Command::Read(base) => {
+                self.log("reading configuration".to_string());
+                match self.run_menus(|expect| menu::read(expect, &base)) {
+                    Ok(settings) => {
+                        self.log("read configuration from board".to_string());
+                        self.emit(Event::Settings(settings));
+                        self.succeeded()
+                    }
+                    Err(e) => self.report_error("read", e),
+                }
+            }
+            Command::Probe => {
+                self.scan_ports();
+                let candidates = self.probe_candidates();
+                for (port, _) in candidates.iter() {
+                    probe::forget(port);
+                }
+                probe::request(&candidates);
+                while probe::pending(&candidates) {
+                    if self.jobs.cancel.load(Ordering::SeqCst) {
+                        return JobStatus::Cancelled;
+                    }
+                    thread::sleep(Duration::from_millis(100));
+                }
+                self.update_probes(&candidates);
+                JobStatus::Succeeded
+            }
+            Command::Send(data) => self.send_line(data),
+            Command::SendBytes(data) => {
+                let Some(serial) = self.serial.as_mut() else {
+                    return self.report_error("send", SerialError::NotConnected);
+                };
+
+                match serial.write_all(&data).and_then(|_| serial.flush()) {
+                    Ok(()) => {
+                        self.emit(Event::Line(Line::new(Direction::Tx, raw::escape(&data))));
+                        JobStatus::Succeeded
+                    }
+                    Err(error) => self.report_error("send", SerialError::Io { step: "send bytes".to_string(), error }),
+                }
+            }
+            Command::Sketch(command) => self.send_line(command.encode()),
+            Command::Replay(path) => match Recording::load(&path) {
+                Ok(recording) => {
+                    self.replay(recording);
+                    JobStatus::Succeeded
+                }
+                Err(error) => {
+                    self.log(format!("replay failed: {}", error));
+                    JobStatus::Failed(error.to_string())
+                }
+            },
+        }
+    }
+
+    // Shows a recording as if it was received now, with the times it was
+    // recorded at
+    fn replay(&mut self, recording: Recording) {
+        self.log(format!("replay of {}, {} chunks", recording.port, recording.chunks.len()));
+        let mut lines = LineAssembler::default();
+        for chunk in recording.chunks {
+            if chunk.direction == Direction::Tx {
+                let mut line = Line::new(Direction::Tx, raw::escape(&chunk.data));
+                line.time = chunk.time;
+                self.emit(Event::Line(line));
+            } else {
+                for line in lines.push_at(&chunk.data, chunk.time) {
+                    self.emit(Event::Line(line));
+                }
+            }
+            self.emit(Event::Bytes(chunk));
+        }
+        if let Some(line) = lines.partial() {
+            self.emit(Event::Line(line));
+        }
+        self.log("end of replay".to_string());
+    }
+
+    fn send_line(&mut self, line: String) -> JobStatus {
+        let Some(serial) = self.serial.as_mut() else {
+            return self.report_error("send", SerialError::NotConnected);
+        };
+
+        let data = format!("{}{}", line, self.profile.line_ending.as_str());
+        let result = serial.write_all(data.as_bytes()).and_then(|_| serial.flush());
+        match result {
+            Ok(()) => {
+                self.emit(Event::Line(Line::new(Direction::Tx, line)));
+                JobStatus::Succeeded
+            }
+            Err(error) => self.report_error("send", SerialError::Io { step: "send".to_string(), error }),
+        }
+    }
+
+    // Runs a menu operation, the board's output and progress messages go to
+    // the console. A cancelled or stalled operation tries to bring the board
+    // back to the sketch.
+    fn run_menus<T>(&mut self, f: impl FnOnce(&mut Expect) -> Result<T, SerialError>) -> Result<T, SerialError> {
+        let transport = self.serial.as_mut().ok_or(SerialError::NotConnected)?;
+
+        self.snapshot.busy = true;
+        let _ = self.events.send(Event::State(self.snapshot.clone()));
+
+        let events = self.events.clone();
+        let lines = &mut self.lines;
+        let mut job = self.job.clone();
+        // e.g. saving the settings restarts the board
+        let mut restarted = false;
+        let mut output = |output: Output| {
+            let _ = events.send(match output {
+                Output::Received(data) => {
+                    restarted |= received(lines, &events, data);
+                    return;
+                }
+                Output::Progress(message) => Event::Line(Line::new(Direction::Local, message.to_string())),
+                Output::Fraction(fraction) => match &mut job {
+                    Some(job) => {
+                        job.progress = Some(fraction);
+                        Event::Job(job.clone())
+                    }
+                    None => return,
+                },
+            });
+        };
+        let mut expect = Expect::new(transport.as_mut(), &mut output).with_cancel(self.jobs.cancel.clone());
+        let result = f(&mut expect);
+
+        if let Err(SerialError::Cancelled { .. } | SerialError::Timeout { .. }) = &result {
+            self.jobs.cancel.store(false, Ordering::SeqCst);
+            if let Err(e) = menu::recover(&mut expect) {
+                expect.progress(&format!("could not leave the menus: {}", e));
+            }
+        }
+        drop(expect);
+
+        self.snapshot.busy = false;
+        self.publish();
+        if restarted {
+            self.board_restarted();
+        }
+        result
+    }
+
+    fn read_board(&mut self) {
+        let Some(serial) = self.serial.as_mut() else { return };
+        let mut buffer = [0; 256];
+
+        match serial.read(&mut buffer) {
+            Ok(0) => {
+                self.report_error("read", SerialError::Closed { step: "read".to_string() });
+            }
+            Ok(read) => {
+                if let Some(pending) = &mut self.startup_pending {
+                    *pending = Instant::now();
+                }
+                if received(&mut self.lines, &self.events, &buffer[..read]) {
+                    self.board_restarted();
+                }
+            }
+            Err(error) => {
+                let error = SerialError::Io { step: "read".to_string(), error };
+                if error.is_fatal() {
+                    self.report_error("read", error);
+                }
+            }
+        }
+    }
+}
+
+// Passes bytes from the board on as lines, values of subscribed inputs are
+// picked out of them. True if the sketch printed its prompt, which it only
+// does after a restart.
+fn received(lines: &mut LineAssembler, events: &Sender<Event>, data: &[u8]) -> bool {
+    let mut ready = false;
+    for line in lines.push(data) {
+        match SketchEvent::parse(&line.text) {
+            SketchEvent::Value { name, value } => {
+                let _ = events.send(Event::Value { name, value });
+            }
+            SketchEvent::Ready => ready = true,
+            _ => {}
+        }
+        let _ = events.send(Event::Line(line));
+    }
+
+    if let Some(partial) = lines.partial() {
+        let _ = events.send(Event::Line(partial));
+    }
+    ready
+}
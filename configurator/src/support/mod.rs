@@ -16,7 +16,6 @@ pub struct System {
     pub imgui: Context,
     pub platform: WinitPlatform,
     pub renderer: Renderer,
-    pub font_size: f32,
 }
 
 pub fn init(title: &str) -> System {
@@ -102,7 +101,6 @@ pub fn init(title: &str) -> System {
         imgui,
         platform,
         renderer,
-        font_size
     }
 }
 
@@ -0,0 +1,86 @@
+use std::collections::BTreeMap;
+use serde_json::Value;
+use crate::serial::{Settings, UsbDevice};
+
+// Any text of a preset may hold placeholders like ${station}, which makes
+// the preset a template. They are filled in when it is applied.
+
+// The placeholders of a preset, each once
+pub fn variables(settings: &Settings) -> Vec<String> {
+    let mut names = vec![];
+    if let Ok(value) = serde_json::to_value(settings) {
+        collect(&value, &mut names);
+    }
+    names
+}
+
+fn collect(value: &Value, names: &mut Vec<String>) {
+    match value {
+        Value::String(text) => {
+            for name in placeholders(text) {
+                if !names.iter().any(|x| x == name) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        Value::Array(values) => values.iter().for_each(|x| collect(x, names)),
+        Value::Object(fields) => fields.values().for_each(|x| collect(x, names)),
+        _ => {}
+    }
+}
+
+fn placeholders(text: &str) -> Vec<&str> {
+    let mut names = vec![];
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else { break };
+        names.push(&rest[start + 2..start + end]);
+        rest = &rest[start + end + 1..];
+    }
+    names
+}
+
+// Values a board brings along, e.g. for hostnames that follow the adapter
+pub fn board_values(port: &str, device: Option<&UsbDevice>) -> BTreeMap<String, String> {
+    let mut values = BTreeMap::new();
+    values.insert("port".to_string(), port.to_string());
+    if let Some(serial) = device.and_then(|x| x.serial.clone()) {
+        values.insert("serial".to_string(), serial);
+    }
+    values
+}
+
+// The preset with all placeholders replaced, an error names the first one
+// without a value
+pub fn fill(settings: &Settings, values: &BTreeMap<String, String>) -> Result<Settings, String> {
+    let mut value = serde_json::to_value(settings).map_err(|e| e.to_string())?;
+    replace(&mut value, values)?;
+    serde_json::from_value(value).map_err(|e| e.to_string())
+}
+
+fn replace(value: &mut Value, values: &BTreeMap<String, String>) -> Result<(), String> {
+    match value {
+        Value::String(text) => {
+            *text = fill_text(text, values)?;
+            Ok(())
+        }
+        Value::Array(items) => items.iter_mut().try_for_each(|x| replace(x, values)),
+        Value::Object(fields) => fields.values_mut().try_for_each(|x| replace(x, values)),
+        _ => Ok(()),
+    }
+}
+
+fn fill_text(text: &str, values: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut filled = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else { break };
+        let name = &rest[start + 2..start + end];
+        let value = values.get(name).ok_or_else(|| format!("no value for ${{{}}}", name))?;
+        filled.push_str(&rest[..start]);
+        filled.push_str(value);
+        rest = &rest[start + end + 1..];
+    }
+    filled.push_str(rest);
+    Ok(filled)
+}
@@ -0,0 +1,599 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use crossterm::event::{self, Event as TermEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction as Split, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Span, Spans};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, Terminal};
+use crate::bridges::Bridges;
+use crate::console;
+use crate::devices::Bindings;
+use crate::preset;
+use crate::profiles::Profiles;
+use crate::serial::simulator::Simulator;
+use crate::serial::{Command, Direction, JobStatus, Line, Worker, WorkerOptions};
+use crate::template;
+use crate::{board_values, handle_event, open_preset, port_key, port_label, preset_names, SettingsForm, State};
+
+// How long to wait for a key before the worker's events are taken in
+const TICK: Duration = Duration::from_millis(50);
+
+// The panes of the window version, in the order Tab goes through them
+#[derive(Clone, Copy, PartialEq)]
+enum Pane {
+    Controls,
+    Configurator,
+    Presets,
+    Console,
+}
+
+const PANES: [Pane; 4] = [Pane::Controls, Pane::Configurator, Pane::Presets, Pane::Console];
+
+// A row of a pane
+#[derive(Clone, Copy, PartialEq)]
+enum Item {
+    Port,
+    Profile,
+    Connect,
+    Probe,
+    Command,
+    Ssid,
+    Password,
+    RgbLedNum,
+    CreateSwarm,
+    SwarmName,
+    SwarmPin,
+    Hostname,
+    SwarmType,
+    Input(usize),
+    Output(usize),
+    Led(usize),
+    Servo,
+    // placeholder of a template preset that is being applied
+    Variable(usize),
+    Apply,
+    FullApply,
+    Read,
+    Cancel,
+    Preset(usize),
+    NewPreset,
+}
+
+struct App {
+    config_dir: PathBuf,
+    worker: Worker,
+    state: State,
+    form: SettingsForm,
+    bindings: Bindings,
+    profiles: Profiles,
+    command: String,
+    new_preset: String,
+    pane: Pane,
+    // selected row of each pane
+    rows: [usize; 4],
+    // row being edited and its text so far
+    editing: Option<(Item, String)>,
+    // placeholders asked for before a template preset is applied, they
+    // take the place of the configurator rows
+    template: Option<Vec<(String, String)>>,
+    // hex dump instead of lines in the console
+    raw_view: bool,
+    // console lines scrolled back from the end
+    scroll: usize,
+    quit: bool,
+}
+
+// Runs the terminal frontend until it is quit
+pub fn run(config_dir: &Path) -> io::Result<()> {
+    // warnings about the settings files still show before the alternate screen
+    let mut app = App::new(config_dir);
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+
+    // a panic message would be lost on the alternate screen
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore();
+        hook(info);
+    }));
+
+    let result = Terminal::new(CrosstermBackend::new(io::stdout())).and_then(|mut terminal| app.run(&mut terminal));
+    restore();
+    result
+}
+
+fn restore() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen);
+}
+
+fn checkbox(checked: bool) -> &'static str {
+    if checked {
+        "[x]"
+    } else {
+        "[ ]"
+    }
+}
+
+impl App {
+    fn new(config_dir: &Path) -> App {
+        let bridges = Bridges::load(&config_dir.join("network").join("bridges.json"));
+        let worker = Worker::spawn(Simulator::new(100), WorkerOptions { bridges: bridges.ports().to_vec(), ..WorkerOptions::default() });
+
+        App {
+            config_dir: config_dir.to_path_buf(),
+            worker,
+            state: State::default(),
+            form: SettingsForm::default(),
+            bindings: Bindings::load(&config_dir.join("devices").join("bindings.json")),
+            profiles: Profiles::load(&config_dir.join("profiles").join("profiles.json")),
+            command: String::new(),
+            new_preset: String::new(),
+            pane: Pane::Controls,
+            rows: [0; 4],
+            editing: None,
+            template: None,
+            raw_view: false,
+            scroll: 0,
+            quit: false,
+        }
+    }
+
+    fn run<B: Backend>(&mut self, terminal: &mut Terminal<B>) -> io::Result<()> {
+        while !self.quit {
+            for event in self.worker.poll() {
+                handle_event(&mut self.state, event);
+            }
+
+            // the same as the settings pane of the window version does
+            if self.state.should_apply {
+                self.form.load(&self.state.settings);
+                self.state.should_apply = false;
+            }
+            self.form.fit_lists();
+            self.state.settings = self.form.settings();
+
+            terminal.draw(|f| self.draw(f))?;
+
+            if event::poll(TICK)? {
+                if let TermEvent::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.key(key);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn log(&mut self, message: String) {
+        self.state.console.push(Line::new(Direction::Local, message));
+    }
+
+    fn connected(&self) -> bool {
+        self.state.worker.connected.is_some()
+    }
+
+    fn items(&self, pane: Pane) -> Vec<Item> {
+        match pane {
+            Pane::Controls => vec![Item::Port, Item::Profile, Item::Connect, Item::Probe, Item::Command],
+            Pane::Configurator if self.template.is_some() => {
+                let mut items: Vec<Item> = (0..self.template.as_ref().map_or(0, |x| x.len())).map(Item::Variable).collect();
+                items.extend([Item::Apply, Item::Cancel]);
+                items
+            }
+            Pane::Configurator => {
+                let mut items = vec![
+                    Item::Ssid,
+                    Item::Password,
+                    Item::RgbLedNum,
+                    Item::CreateSwarm,
+                    Item::SwarmName,
+                    Item::SwarmPin,
+                    Item::Hostname,
+                    Item::SwarmType,
+                ];
+                items.extend((0..self.form.input_list.len()).map(Item::Input));
+                items.extend((0..self.form.output_list.len()).map(Item::Output));
+                items.extend((0..self.form.led_list.len()).map(Item::Led));
+                items.extend([Item::Servo, Item::Apply, Item::FullApply, Item::Read, Item::Cancel]);
+                items
+            }
+            Pane::Presets => {
+                let mut items: Vec<Item> = (0..preset_names(&self.config_dir).len()).map(Item::Preset).collect();
+                items.push(Item::NewPreset);
+                items
+            }
+            Pane::Console => vec![],
+        }
+    }
+
+    // Value of a row that is edited as text
+    fn text(&self, item: Item) -> Option<String> {
+        let form = &self.form;
+        Some(match item {
+            Item::Command => self.command.clone(),
+            Item::Ssid => form.ssid.clone(),
+            Item::Password => form.password.clone(),
+            Item::RgbLedNum => form.rgb_led_num.to_string(),
+            Item::SwarmName => form.swarm_name.clone(),
+            Item::SwarmPin => form.swarm_pin.clone(),
+            Item::Hostname => form.hostname.clone(),
+            Item::Input(i) => form.input_list[i].clone(),
+            Item::Output(i) => form.output_list[i].clone(),
+            Item::Led(i) => form.led_list[i].clone(),
+            Item::Servo => form.servo_port.clone(),
+            Item::Variable(i) => self.template.as_ref()?.get(i)?.1.clone(),
+            Item::NewPreset => self.new_preset.clone(),
+            _ => return None,
+        })
+    }
+
+    fn set_text(&mut self, item: Item, value: String) {
+        let form = &mut self.form;
+        match item {
+            Item::Command => self.command = value,
+            Item::Ssid => form.ssid = value,
+            Item::Password => form.password = value,
+            Item::RgbLedNum => match value.trim().parse() {
+                Ok(number) => form.rgb_led_num = number,
+                Err(_) => self.log(format!("not a number: {}", value)),
+            },
+            Item::SwarmName => form.swarm_name = value,
+            Item::SwarmPin => form.swarm_pin = value,
+            Item::Hostname => form.hostname = value,
+            Item::Input(i) => form.input_list[i] = value,
+            Item::Output(i) => form.output_list[i] = value,
+            Item::Led(i) => form.led_list[i] = value,
+            Item::Servo => form.servo_port = value,
+            Item::Variable(i) => {
+                if let Some(variable) = self.template.as_mut().and_then(|x| x.get_mut(i)) {
+                    variable.1 = value;
+                }
+            }
+            Item::NewPreset => self.new_preset = value,
+            _ => {}
+        }
+    }
+
+    // The row as shown, with the text so far while it is edited
+    fn label(&self, item: Item) -> String {
+        let label = self.describe(item);
+        match &self.editing {
+            Some((edited, text)) if *edited == item => {
+                let name = label.split(':').next().unwrap_or_default();
+                format!("{}: {}_", name, text)
+            }
+            _ => label,
+        }
+    }
+
+    fn describe(&self, item: Item) -> String {
+        let form = &self.form;
+        match item {
+            Item::Port if self.state.port.is_empty() => "port: (←/→ to choose)".to_string(),
+            Item::Port => format!("port: {}", port_label(&self.state.worker, &self.bindings, &self.state.port)),
+            Item::Profile => {
+                let key = port_key(&self.state.worker, &self.state.port);
+                format!("profile: {}", self.profiles.name_for_port(&key))
+            }
+            Item::Connect if self.connected() => "[disconnect]".to_string(),
+            Item::Connect => "[connect]".to_string(),
+            Item::Probe => "[probe ports]".to_string(),
+            Item::Command => format!("command: {}", self.command),
+            Item::Ssid => format!("ssid: {}", form.ssid),
+            Item::Password => format!("password: {}", form.password),
+            Item::RgbLedNum => format!("rgb led num: {}", form.rgb_led_num),
+            Item::CreateSwarm => format!("create swarm: {}", checkbox(form.create_swarm)),
+            Item::SwarmName => format!("swarm name: {}", form.swarm_name),
+            Item::SwarmPin => format!("swarm pin: {}", form.swarm_pin),
+            Item::Hostname => format!("hostname: {}", form.hostname),
+            Item::SwarmType => format!("swarm type: {}", if form.swarm_type == 0 { "JST" } else { "RS485" }),
+            Item::Input(i) => format!("A{}: {}", i + 1, form.input_list[i]),
+            Item::Output(i) => format!("M{}: {}", i + 1, form.output_list[i]),
+            Item::Led(i) => format!("LED{}: {}", i + 1, form.led_list[i]),
+            Item::Servo => format!("SERVO: {}", form.servo_port),
+            Item::Variable(i) => match self.template.as_ref().and_then(|x| x.get(i)) {
+                Some((name, value)) => format!("{}: {}", name, value),
+                None => String::new(),
+            },
+            Item::Apply => "[apply]".to_string(),
+            Item::FullApply => format!("rewrite everything: {}", checkbox(form.full_apply)),
+            Item::Read => "[read from board]".to_string(),
+            Item::Cancel => "[cancel]".to_string(),
+            Item::Preset(i) => preset_names(&self.config_dir).get(i).cloned().unwrap_or_default(),
+            Item::NewPreset => format!("new preset: {}", self.new_preset),
+        }
+    }
+
+    fn selected(&self) -> Option<Item> {
+        let items = self.items(self.pane);
+        let index = self.rows[self.pane as usize].min(items.len().saturating_sub(1));
+        items.get(index).copied()
+    }
+
+    fn key(&mut self, key: KeyEvent) {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            self.quit = true;
+            return;
+        }
+
+        if let Some((item, mut text)) = self.editing.take() {
+            match key.code {
+                KeyCode::Enter => self.commit(item, text),
+                KeyCode::Esc => {}
+                KeyCode::Backspace => {
+                    text.pop();
+                    self.editing = Some((item, text));
+                }
+                KeyCode::Char(c) => {
+                    text.push(c);
+                    self.editing = Some((item, text));
+                }
+                _ => self.editing = Some((item, text)),
+            }
+            return;
+        }
+
+        let rows = self.items(self.pane).len();
+        let row = &mut self.rows[self.pane as usize];
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Tab | KeyCode::BackTab => {
+                let index = PANES.iter().position(|x| *x == self.pane).unwrap_or(0);
+                let step = if key.code == KeyCode::Tab { 1 } else { PANES.len() - 1 };
+                self.pane = PANES[(index + step) % PANES.len()];
+            }
+            KeyCode::Up if self.pane == Pane::Console => self.scroll += 1,
+            KeyCode::Down if self.pane == Pane::Console => self.scroll = self.scroll.saturating_sub(1),
+            KeyCode::PageUp if self.pane == Pane::Console => self.scroll += 20,
+            KeyCode::PageDown if self.pane == Pane::Console => self.scroll = self.scroll.saturating_sub(20),
+            KeyCode::End if self.pane == Pane::Console => self.scroll = 0,
+            KeyCode::Char('r') if self.pane == Pane::Console => self.raw_view = !self.raw_view,
+            KeyCode::Up => *row = row.saturating_sub(1),
+            KeyCode::Down => *row = (*row + 1).min(rows.saturating_sub(1)),
+            KeyCode::Left => self.choose(false),
+            KeyCode::Right => self.choose(true),
+            KeyCode::Enter => self.activate(),
+            KeyCode::Delete => self.delete_preset(),
+            _ => {}
+        }
+    }
+
+    // ←/→ on the port and profile rows
+    fn choose(&mut self, forward: bool) {
+        let step = |index: Option<usize>, len: usize| match (index, forward) {
+            (None, _) => 0,
+            (Some(i), true) => (i + 1) % len,
+            (Some(i), false) => (i + len - 1) % len,
+        };
+
+        match self.selected() {
+            Some(Item::Port) if !self.connected() => {
+                let ports = &self.state.worker.ports;
+                if !ports.is_empty() {
+                    let index = step(ports.iter().position(|x| *x == self.state.port), ports.len());
+                    self.state.port = ports[index].clone();
+                    self.state.current_port = index + 1;
+                }
+            }
+            Some(Item::Profile) if !self.connected() => {
+                let key = port_key(&self.state.worker, &self.state.port);
+                let names = self.profiles.names();
+                let index = step(names.iter().position(|x| *x == self.profiles.name_for_port(&key)), names.len());
+                if let Err(e) = self.profiles.assign(&key, &names[index]) {
+                    self.log(format!("could not save the profiles: {}", e));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn activate(&mut self) {
+        let Some(item) = self.selected() else { return };
+        if let Some(text) = self.text(item) {
+            self.editing = Some((item, text));
+            return;
+        }
+
+        let busy = self.state.worker.busy;
+        match item {
+            Item::Port | Item::Profile => self.choose(true),
+            Item::Connect if busy => {}
+            Item::Connect if self.connected() => {
+                self.worker.send(Command::Disconnect);
+            }
+            Item::Connect => {
+                // a board with a preset of its own gets it in the form
+                let port = self.state.port.clone();
+                let device = self.state.worker.devices.get(&port);
+                let preset = device.and_then(|x| self.bindings.get(&x.identity())).and_then(|x| x.preset.clone());
+                if let Some(preset) = preset {
+                    open_preset(&mut self.state, &self.config_dir, &preset);
+                }
+                let key = port_key(&self.state.worker, &port);
+                self.worker.send(Command::Connect(port, self.profiles.for_port(&key)));
+            }
+            Item::Probe if !busy && !self.connected() => {
+                self.worker.send(Command::Probe);
+            }
+            Item::CreateSwarm => self.form.create_swarm = !self.form.create_swarm,
+            Item::SwarmType => self.form.swarm_type = if self.form.swarm_type == 0 { 1 } else { 0 },
+            Item::FullApply => self.form.full_apply = !self.form.full_apply,
+            Item::Apply if self.connected() => self.apply(),
+            Item::Read if self.connected() => {
+                self.worker.send(Command::Read(self.state.settings.clone()));
+            }
+            Item::Cancel if self.template.is_some() => self.template = None,
+            Item::Cancel => {
+                if let Some(job) = self.state.jobs.iter().find(|x| x.status == JobStatus::Running) {
+                    self.worker.cancel(job.id);
+                }
+            }
+            Item::Preset(i) => {
+                if let Some(name) = preset_names(&self.config_dir).get(i) {
+                    open_preset(&mut self.state, &self.config_dir, name);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Template presets ask for their placeholders first
+    fn apply(&mut self) {
+        let mut values = board_values(&self.state.worker);
+        match self.template.take() {
+            Some(variables) => values.extend(variables),
+            None => {
+                let missing: Vec<(String, String)> = template::variables(&self.state.settings)
+                    .into_iter()
+                    .filter(|x| !values.contains_key(x))
+                    .map(|x| (x, String::new()))
+                    .collect();
+                if !missing.is_empty() {
+                    self.template = Some(missing);
+                    self.rows[Pane::Configurator as usize] = 0;
+                    return;
+                }
+            }
+        }
+
+        match template::fill(&self.state.settings, &values) {
+            Ok(settings) => {
+                self.worker.send(Command::Apply(settings, self.form.full_apply));
+            }
+            Err(e) => self.log(e),
+        }
+    }
+
+    fn commit(&mut self, item: Item, text: String) {
+        match item {
+            Item::Command => {
+                if self.connected() {
+                    self.worker.send(crate::command(&text));
+                    self.command.clear();
+                } else {
+                    self.command = text;
+                }
+            }
+            Item::NewPreset => {
+                let name = text.trim().to_string();
+                if !name.is_empty() {
+                    if let Err(e) = self.save_preset(&name) {
+                        self.log(format!("could not save preset {}: {}", name, e));
+                    }
+                }
+                self.new_preset.clear();
+            }
+            _ => self.set_text(item, text),
+        }
+    }
+
+    fn save_preset(&self, name: &str) -> io::Result<()> {
+        preset::save(&self.config_dir.join(name), &self.state.settings)
+    }
+
+    fn delete_preset(&mut self) {
+        let Some(Item::Preset(i)) = self.selected() else { return };
+        let Some(name) = preset_names(&self.config_dir).get(i).cloned() else { return };
+        if let Err(e) = std::fs::remove_file(self.config_dir.join(&name)) {
+            self.log(format!("could not delete preset {}: {}", name, e));
+        }
+    }
+
+    fn draw<B: Backend>(&mut self, f: &mut Frame<B>) {
+        let screen = Layout::default()
+            .direction(Split::Vertical)
+            .constraints([Constraint::Min(10), Constraint::Length(2)])
+            .split(f.size());
+        let columns = Layout::default()
+            .direction(Split::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(screen[0]);
+        let left = Layout::default()
+            .direction(Split::Vertical)
+            .constraints([Constraint::Length(7), Constraint::Min(8), Constraint::Length(8)])
+            .split(columns[0]);
+
+        self.draw_list(f, Pane::Controls, "controls", left[0]);
+        let title = if self.template.is_some() { "template variables" } else { "swarm configurator" };
+        self.draw_list(f, Pane::Configurator, title, left[1]);
+        self.draw_list(f, Pane::Presets, "presets (Del deletes)", left[2]);
+        self.draw_console(f, columns[1]);
+        self.draw_status(f, screen[1]);
+    }
+
+    fn block(&self, pane: Pane, title: &str) -> Block<'static> {
+        let style = if self.pane == pane { Style::default().fg(Color::Yellow) } else { Style::default() };
+        Block::default().borders(Borders::ALL).border_style(style).title(title.to_string())
+    }
+
+    fn draw_list<B: Backend>(&self, f: &mut Frame<B>, pane: Pane, title: &str, area: Rect) {
+        let items: Vec<ListItem> = self.items(pane).into_iter().map(|x| ListItem::new(self.label(x))).collect();
+        let mut state = ListState::default();
+        if self.pane == pane {
+            state.select(Some(self.rows[pane as usize].min(items.len().saturating_sub(1))));
+        }
+
+        let list = List::new(items)
+            .block(self.block(pane, title))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        f.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn draw_console<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect) {
+        let height = area.height.saturating_sub(2) as usize;
+        let len = if self.raw_view { self.state.raw.len() } else { self.state.console.len() };
+        self.scroll = self.scroll.min(len.saturating_sub(height));
+        let end = len - self.scroll;
+        let start = end.saturating_sub(height);
+
+        let lines: Vec<Spans> = (start..end)
+            .filter_map(|i| {
+                if self.raw_view {
+                    return self.state.raw.get(i).map(|x| Spans::from(x.clone()));
+                }
+                let line = self.state.console.get(i)?;
+                let style = match line.direction {
+                    Direction::Rx => Style::default(),
+                    Direction::Tx => Style::default().fg(Color::Cyan),
+                    Direction::Local => Style::default().fg(Color::Yellow),
+                };
+                Some(Spans::from(Span::styled(console::format_line(line), style)))
+            })
+            .collect();
+
+        let title = if self.raw_view { "console (raw bytes, r toggles)" } else { "console (r for raw bytes)" };
+        f.render_widget(Paragraph::new(lines).block(self.block(Pane::Console, title)), area);
+    }
+
+    fn draw_status<B: Backend>(&self, f: &mut Frame<B>, area: Rect) {
+        let worker = &self.state.worker;
+        let status = if let Some(error) = &worker.last_error {
+            Span::styled(error.clone(), Style::default().fg(Color::Red))
+        } else if let Some(port) = &worker.lost {
+            Span::styled(format!("lost connection to {}", port), Style::default().fg(Color::Yellow))
+        } else if let Some(job) = self.state.jobs.iter().rev().find(|x| !x.status.is_finished()) {
+            Span::raw(format!("{}: {}", job.status, job.label))
+        } else if let Some(port) = &worker.connected {
+            Span::raw(format!("connected to {}", port))
+        } else {
+            Span::raw("not connected")
+        };
+
+        let values: String = self.state.input_values.iter().map(|(name, value)| format!("  {}: {}", name, value)).collect();
+        let help = if self.editing.is_some() {
+            "Enter: done  Esc: cancel  Ctrl-C: quit"
+        } else {
+            "Tab: next pane  ↑/↓: select  Enter: edit or press  ←/→: choose  q: quit"
+        };
+
+        let text = vec![Spans::from(vec![status, Span::raw(values)]), Spans::from(Span::styled(help, Style::default().add_modifier(Modifier::DIM)))];
+        f.render_widget(Paragraph::new(text), area);
+    }
+}